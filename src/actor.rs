@@ -0,0 +1,184 @@
+use std::net::SocketAddr;
+use std::process;
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::thread::JoinHandle;
+use std::time;
+
+use num_bigint::BigInt;
+
+use super::admin::NodeStatus;
+use super::dashboard::{Snapshot, Window};
+use super::lifecycle::NodeState;
+use super::logging;
+use super::logging::MessageContext;
+use super::network;
+use super::node::{Node, OtherNode};
+use super::operation::OperationReply;
+use super::print;
+use super::protocols::{Effect, Message, Request};
+use super::snapshot::{ExportEvent, ImportEvent};
+use super::storage::{DHTEntry, LogEntry};
+
+/// Commands and events processed by the node actor, the only owner of the node state
+pub enum Command {
+    /// Message received by the listener, boxed as messages are much larger than the other commands
+    Incoming(Box<Message>),
+    /// Try to join the ring via the entry point, the reply tells whether the node has joined already
+    Join {
+        entry_point: SocketAddr,
+        reply: Sender<bool>,
+    },
+    GetState {
+        reply: Sender<NodeState>,
+    },
+    /// Id and address of the node, the id changes if the node joined with a salted id
+    GetNode {
+        reply: Sender<OtherNode>,
+    },
+    GetStatus {
+        reply: Sender<NodeStatus>,
+    },
+    /// Copy of the node state with the visible rows of the storage and log panes
+    GetSnapshot {
+        storage: Window,
+        log: Window,
+        reply: Sender<Snapshot>,
+    },
+    /// Storage log entries starting at index `since`, and the index of the next entry
+    GetLog {
+        since: usize,
+        reply: Sender<(Vec<LogEntry>, usize)>,
+    },
+    Stabilize,
+    FixFingers,
+    CheckPredecessor,
+    ExpireKeys,
+    CheckRequests,
+    PingPeers,
+    RenewLeases,
+    /// Report changes of the key in the storage log
+    Watch {
+        key: String,
+    },
+    Unwatch {
+        key: String,
+    },
+    /// Report the messages published to the topic in the storage log
+    Subscribe {
+        topic: String,
+    },
+    Unsubscribe {
+        topic: String,
+    },
+    /// Collect the keys of this node, or of all nodes by walking the ring
+    Export {
+        ring: bool,
+        events: Sender<ExportEvent>,
+    },
+    /// Store the entries via the node responsible for each key
+    Import {
+        data: Vec<(BigInt, DHTEntry)>,
+        events: Sender<ImportEvent>,
+    },
+    /// Request of the user, sent from this node. The reply gets its result
+    Request {
+        request: Box<Request>,
+        reply: Option<OperationReply>,
+    },
+    Print,
+    /// Hand over all keys to the successor and terminate the process
+    Shutdown,
+}
+
+/// Processes commands one after another until all senders are gone
+pub fn run(mut node: Node, commands: Receiver<Command>) {
+    for command in commands {
+        logging::set_node_id(node.get_id());
+        let effects = match command {
+            Command::Incoming(message) => {
+                let _context = MessageContext::enter(&message);
+                node.handle_message(*message)
+            }
+            Command::Join { entry_point, reply } => {
+                let effects = if !node.is_joined() {
+                    node.join(entry_point)
+                } else {
+                    Vec::new()
+                };
+                let _ = reply.send(node.is_joined());
+                effects
+            }
+            Command::GetState { reply } => {
+                let _ = reply.send(node.get_state());
+                Vec::new()
+            }
+            Command::GetNode { reply } => {
+                let _ = reply.send(node.to_other_node());
+                Vec::new()
+            }
+            Command::GetStatus { reply } => {
+                let _ = reply.send(NodeStatus::new(&node));
+                Vec::new()
+            }
+            Command::GetSnapshot { storage, log, reply } => {
+                let _ = reply.send(Snapshot::new(&node, storage, log));
+                Vec::new()
+            }
+            Command::GetLog { since, reply } => {
+                let _ = reply.send(node.get_storage().get_log_entries_since(since));
+                Vec::new()
+            }
+            Command::Stabilize => node.stabilize(),
+            Command::FixFingers => node.fix_next_finger(),
+            Command::CheckPredecessor => node.check_predecessor(),
+            Command::ExpireKeys => node.expire_keys(),
+            Command::CheckRequests => node.check_pending_requests(),
+            Command::PingPeers => node.ping_peers(),
+            Command::RenewLeases => node.renew_leases(),
+            Command::Watch { key } => node.watch_key(key),
+            Command::Unwatch { key } => node.unwatch_key(key),
+            Command::Subscribe { topic } => node.subscribe(topic),
+            Command::Unsubscribe { topic } => node.unsubscribe(topic),
+            Command::Export { ring, events } => node.export_keys(ring, events),
+            Command::Import { data, events } => node.import_keys(data, events),
+            Command::Request { request, reply } => node.send_user_request(*request, reply),
+            Command::Print => {
+                if node.is_joined() {
+                    print::print_current_node_state(&node)
+                }
+                Vec::new()
+            }
+            Command::Shutdown => node.graceful_shutdown(),
+        };
+        execute(effects);
+    }
+}
+
+/// Executes the effects returned by the node logic in order
+fn execute(effects: Vec<Effect>) {
+    let mut handles = Vec::new();
+    for effect in effects {
+        match effect {
+            Effect::Send { target, message } => handles.push(network::send_message(target, *message)),
+            Effect::Exit { code } => {
+                // Make sure pending messages, e.g. the key hand over, are sent before exiting
+                for handle in handles.drain(..) {
+                    let _ = handle.join();
+                }
+                process::exit(code);
+            }
+        }
+    }
+}
+
+/// Sends a command to the actor every `interval`, until the actor is gone
+pub fn spawn_timer<F>(name: &str, sender: Sender<Command>, interval: time::Duration, command: F) -> JoinHandle<()>
+    where F: Fn() -> Command + Send + 'static {
+    thread::Builder::new().name(name.to_string())
+        .spawn(move || {
+            while sender.send(command()).is_ok() {
+                thread::sleep(interval);
+            }
+        }).unwrap()
+}
@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::net;
+use std::net::SocketAddr;
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use num_bigint::BigInt;
+
+use super::actor::Command;
+use super::chord;
+use super::error::ChordError;
+use super::input;
+use super::lifecycle::NodeState;
+use super::node::{Node, OtherNode};
+use super::operation::OperationResult;
+use super::protocols::Request;
+use super::snapshot;
+use super::snapshot::{ExportSummary, ImportSummary};
+use super::storage;
+use super::storage::LogEntry;
+
+/// Request to the admin socket, sent as one JSON object per line
+#[derive(Serialize, Deserialize, Debug)]
+pub enum AdminRequest {
+    Status,
+    Store {
+        key: String,
+        value: String,
+        ttl_secs: Option<u64>,
+    },
+    Find {
+        key: String,
+    },
+    Delete {
+        key: String,
+    },
+    /// Changes of the key show up in the log of the status
+    Watch {
+        key: String,
+    },
+    Unwatch {
+        key: String,
+    },
+    /// Messages published to the topic show up in the log of the status
+    Subscribe {
+        topic: String,
+    },
+    Unsubscribe {
+        topic: String,
+    },
+    Publish {
+        topic: String,
+        message: String,
+    },
+    /// Writes the keys of this node, or of the whole ring, to a JSON lines file on the node's host
+    Export {
+        path: String,
+        ring: bool,
+    },
+    Import {
+        path: String,
+    },
+    Kill {
+        target: SocketAddr,
+    },
+    Shutdown,
+}
+
+/// Answer of the admin socket, sent as one JSON object per line.
+/// DHT operations are answered once their result arrived.
+#[derive(Serialize, Deserialize, Debug)]
+pub enum AdminResponse {
+    Status(NodeStatus),
+    Completed(OperationResult),
+    Exported(ExportSummary),
+    Imported(ImportSummary),
+    Accepted,
+    Error {
+        error: ChordError,
+    },
+}
+
+/// Snapshot of the node state, the daemon counterpart of the printed tables
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NodeStatus {
+    id: BigInt,
+    ip_addr: SocketAddr,
+    state: NodeState,
+    predecessor: Option<OtherNode>,
+    successor_list: Vec<OtherNode>,
+    fingers: Vec<(BigInt, OtherNode)>,
+    keys: usize,
+    log: Vec<String>,
+    rejected_traffic: Vec<(String, usize)>,
+}
+
+impl NodeStatus {
+    pub fn new(node: &Node) -> NodeStatus {
+        NodeStatus {
+            id: node.get_id().clone(),
+            ip_addr: *node.get_ip_addr(),
+            state: node.get_state(),
+            predecessor: node.get_predecessor().clone(),
+            successor_list: node.get_successor_list().clone(),
+            fingers: (0..node.get_finger_table().length())
+                .map(|i| node.get_finger_table().get(i))
+                .map(|entry| (entry.get_id().clone(), entry.get_node().clone()))
+                .collect(),
+            keys: node.get_storage().get_data_as_iter().count(),
+            log: last_log_entries(node.get_storage().get_log_entries()),
+            rejected_traffic: node.get_traffic_stats().get_all().iter()
+                .map(|(rejection, count)| (rejection.to_string(), *count))
+                .collect(),
+        }
+    }
+
+    pub fn get_id(&self) -> &BigInt {
+        &self.id
+    }
+
+    pub fn get_ip_addr(&self) -> &SocketAddr {
+        &self.ip_addr
+    }
+
+    pub fn get_predecessor(&self) -> &Option<OtherNode> {
+        &self.predecessor
+    }
+
+    pub fn get_successor_list(&self) -> &Vec<OtherNode> {
+        &self.successor_list
+    }
+
+    pub fn get_fingers(&self) -> &Vec<(BigInt, OtherNode)> {
+        &self.fingers
+    }
+}
+
+/// Accepts admin connections, each handled by its own thread
+pub fn serve(listener: net::TcpListener, actor: Sender<Command>, node: OtherNode) -> Result<(), ChordError> {
+    info!("Admin socket listening on {}", listener.local_addr()?);
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Unable to accept admin connection: {}", e);
+                continue;
+            }
+        };
+        let actor_clone = actor.clone();
+        let node_clone = node.clone();
+        thread::Builder::new().name("Admin_Connection".to_string())
+            .spawn(move || {
+                if let Err(e) = handle_connection(stream, &actor_clone, &node_clone) {
+                    debug!("Admin connection closed: {}", e);
+                }
+            })?;
+    }
+    Ok(())
+}
+
+fn handle_connection(stream: net::TcpStream, actor: &Sender<Command>, node: &OtherNode) -> Result<(), ChordError> {
+    let reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = serde_json::from_str::<AdminRequest>(&line);
+        let shutdown = matches!(request, Ok(AdminRequest::Shutdown));
+        let response = match request {
+            Ok(request) => {
+                info!("Admin request: {:?}", request);
+                execute(request, actor, node)
+            }
+            Err(e) => AdminResponse::Error { error: e.into() },
+        };
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+        if shutdown {
+            // Answer first, the shutdown terminates the process
+            actor.send(Command::Shutdown).map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+        }
+    }
+    Ok(())
+}
+
+fn execute(request: AdminRequest, actor: &Sender<Command>, node: &OtherNode) -> AdminResponse {
+    match request {
+        AdminRequest::Status => {
+            let (reply, status) = mpsc::channel();
+            if actor.send(Command::GetStatus { reply }).is_err() {
+                return AdminResponse::Error { error: ChordError::Io("node actor stopped".to_string()) };
+            }
+            match status.recv() {
+                Ok(status) => AdminResponse::Status(status),
+                Err(_) => AdminResponse::Error { error: ChordError::Io("node actor stopped".to_string()) },
+            }
+        }
+        AdminRequest::Store { key, value, ttl_secs } => {
+            let data = storage::make_hashed_key_value_pair(key, value, ttl_secs.map(Duration::from_secs));
+            completed(input::execute_request(actor, Request::DHTStoreKey { data }, chord::OPERATION_RESULT_TIMEOUT))
+        }
+        AdminRequest::Find { key } => {
+            let key_id = chord::create_id(&key);
+            completed(input::execute_request(actor, Request::DHTFindKey { key_id }, chord::OPERATION_RESULT_TIMEOUT))
+        }
+        AdminRequest::Delete { key } => {
+            let key_id = chord::create_id(&key);
+            completed(input::execute_request(actor, Request::DHTDeleteKey { key_id }, chord::OPERATION_RESULT_TIMEOUT))
+        }
+        AdminRequest::Watch { key } => match input::watch_key(actor, key) {
+            Ok(()) => AdminResponse::Accepted,
+            Err(error) => AdminResponse::Error { error },
+        },
+        AdminRequest::Unwatch { key } => match input::unwatch_key(actor, key) {
+            Ok(()) => AdminResponse::Accepted,
+            Err(error) => AdminResponse::Error { error },
+        },
+        AdminRequest::Subscribe { topic } => match input::subscribe(actor, topic) {
+            Ok(()) => AdminResponse::Accepted,
+            Err(error) => AdminResponse::Error { error },
+        },
+        AdminRequest::Unsubscribe { topic } => match input::unsubscribe(actor, topic) {
+            Ok(()) => AdminResponse::Accepted,
+            Err(error) => AdminResponse::Error { error },
+        },
+        AdminRequest::Publish { topic, message } => {
+            input::publish(topic, message, node.clone());
+            AdminResponse::Accepted
+        }
+        AdminRequest::Export { path, ring } => match snapshot::export(actor, &path, ring, |line| info!("{}", line)) {
+            Ok(summary) => AdminResponse::Exported(summary),
+            Err(error) => AdminResponse::Error { error },
+        },
+        AdminRequest::Import { path } => match snapshot::import(actor, &path, |line| info!("{}", line)) {
+            Ok(summary) => AdminResponse::Imported(summary),
+            Err(error) => AdminResponse::Error { error },
+        },
+        AdminRequest::Kill { target } => {
+            input::kill_node(target);
+            AdminResponse::Accepted
+        }
+        AdminRequest::Shutdown => AdminResponse::Accepted,
+    }
+}
+
+fn completed(result: Result<OperationResult, ChordError>) -> AdminResponse {
+    match result {
+        Ok(result) => AdminResponse::Completed(result),
+        Err(error) => AdminResponse::Error { error },
+    }
+}
+
+/// Last three entries of the storage log, without the colours of the printed tables
+fn last_log_entries(logs: &VecDeque<LogEntry>) -> Vec<String> {
+    logs.iter().skip(logs.len().saturating_sub(3))
+        .map(|entry| format!("{} {}", entry.get_time().format("%H:%M:%S"), entry.get_message()))
+        .collect()
+}
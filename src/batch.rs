@@ -0,0 +1,51 @@
+use std::collections::HashSet;
+
+use num_bigint::BigInt;
+
+/// Partial results of a batch operation, kept by the node that started it.
+/// The batch knows all of its keys from the start. Every response answers some keys and forwards
+/// the remaining ones to other nodes, keys whose requests failed are recorded as errors.
+/// The batch is complete once every key has a result or an error.
+#[derive(Clone)]
+pub struct PendingBatch<T> {
+    outstanding: HashSet<BigInt>,
+    results: Vec<T>,
+    errors: Vec<String>,
+}
+
+impl<T> PendingBatch<T> {
+    pub fn new(key_ids: Vec<BigInt>) -> PendingBatch<T> {
+        PendingBatch {
+            outstanding: key_ids.into_iter().collect(),
+            results: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    /// Records the answered keys of one response, answers of keys that already have
+    /// a result are dropped. Returns true if the batch is complete afterwards.
+    pub fn add(&mut self, answered: Vec<(BigInt, T)>) -> bool {
+        for (key_id, result) in answered {
+            if self.outstanding.remove(&key_id) {
+                self.results.push(result);
+            }
+        }
+        self.outstanding.is_empty()
+    }
+
+    /// Records keys that failed together with a description of the error,
+    /// returns true if the batch is complete afterwards
+    pub fn fail(&mut self, failed: Vec<(BigInt, String)>) -> bool {
+        for (key_id, error) in failed {
+            if self.outstanding.remove(&key_id) {
+                self.errors.push(error);
+            }
+        }
+        self.outstanding.is_empty()
+    }
+
+    /// Results and errors of the keys
+    pub fn into_results(self) -> (Vec<T>, Vec<String>) {
+        (self.results, self.errors)
+    }
+}
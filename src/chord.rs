@@ -1,25 +1,30 @@
-use std::io::stdin;
-use std::net::SocketAddr;
-use std::{error::Error};
+use std::cmp;
+use std::net;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs};
 use std::process;
-use std::sync::{Arc, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
 use std::thread;
 use std::thread::JoinHandle;
 use std::time;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
 use crypto::digest::Digest;
 use crypto::sha1::Sha1;
 use num::bigint::{BigInt, Sign, ToBigInt};
 use signal_hook::{iterator::Signals, SIGINT};
 
+use super::actor;
+use super::admin;
+use super::actor::Command;
 use super::chord;
-use super::fingertable::*;
+use super::dashboard;
+use super::discovery;
+use super::error::ChordError;
 use super::input::*;
 use super::network;
 use super::node::*;
-use super::print;
-use super::protocols::*;
+use super::script;
 
 pub const CHORD_CIRCLE_BITS: usize = 24;
 
@@ -36,167 +41,150 @@ pub const NODE_FIX_FINGERS_INTERVAL: time::Duration = time::Duration::from_milli
 
 pub const NODE_CHECK_PREDECESSOR_INTERVAL: time::Duration = time::Duration::from_millis(1000);
 
+pub const NODE_EXPIRE_KEYS_INTERVAL: time::Duration = time::Duration::from_millis(1000);
+
 pub const NODE_INIT_SLEEP_INTERVAL: time::Duration = time::Duration::from_millis(2000);
 
-pub const NODE_PRINT_INTERVAL: time::Duration = time::Duration::from_millis(2000);
+/// Upper bound of the wait time between two rounds of join attempts
+pub const JOIN_MAX_BACKOFF_INTERVAL: time::Duration = time::Duration::from_millis(16000);
 
-pub const PING_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_millis(5000);
+/// Time to wait for the answer of one entry point before the next one is tried
+pub const JOIN_ATTEMPT_TIMEOUT: time::Duration = time::Duration::from_millis(5000);
 
-pub const LISTENING_ADDRESS: &str = "0.0.0.0";
+/// Time between two checks whether the node has joined
+pub const JOIN_POLL_INTERVAL: time::Duration = time::Duration::from_millis(100);
 
-pub fn join(id: BigInt, sender: OtherNode, join_ip: SocketAddr) {
-    info!("Trying to join...");
-    let req = Request::FindSuccessor { id };
-    network::send_request(sender, join_ip, req);
-}
+/// Default time after which a node gives up joining via its seed nodes
+pub const JOIN_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_millis(60000);
 
-pub fn stabilize(arc: Arc<Mutex<Node>>) {
-    info!("Starting stabilisation...");
-    loop {
-        debug!("Stabilize.............");
-        // make a copy of node and instantly drop it
-        let node = arc.lock().unwrap();
-        let node_clone = node.clone();
-        drop(node);
-
-        if node_clone.is_joined() {
-            let mut ring_is_alive = false;
-            for succ in node_clone.get_successor_list().clone() {
-                if network::check_alive(*succ.get_ip_addr(), node_clone.to_other_node().clone()) {
-                    let req = Request::GetPredecessor;
-                    network::send_request(node_clone.to_other_node(), *succ.get_ip_addr(), req);
-                    // after async operation check_alive() lock again.
-                    arc.lock().unwrap().update_successor_and_successor_list(succ);
-
-                    ring_is_alive = true;
-                    break;
-                } else {
-                    debug!("Node is dead: {:?}", succ);
-                }
-            }
-            if !ring_is_alive {
-                error!("No functional successor found in successor list. RING IS DEAD. Initializing shutdown...");
-                process::exit(1);
-            }
-        } else {
-            info!("Not joined jet going to sleep again")
-        }
-        //this is super important, because otherwise the lock would persist endlessly due to the loop
-        //node_clone.send_message_to_socket(node_clone.successor.ip_addr, req);
-        thread::sleep(chord::NODE_STABILIZE_INTERVAL);
-    }
-}
+/// Time between two checks of the interactive shell for new results in the storage log
+pub const REPL_LOG_POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
 
-pub fn fix_fingers(arc: Arc<Mutex<Node>>) {
-    debug!("Starting fix_fingers...");
-    let mut next = 1;
-    loop {
-        // make a copy of node and instantly drop it
-        let node = arc.lock().unwrap();
-        let node_clone = node.clone();
-        drop(node);
+/// Time the admin socket and scripts wait for the result of a DHT request, longer than all retries of a request to one node
+pub const OPERATION_RESULT_TIMEOUT: time::Duration = time::Duration::from_millis(30000);
 
-        if node_clone.is_joined() {
-            let finger_id = get_finger_id(node_clone.get_id(), next);
+/// Time an export or import waits for the next answer of the ring before giving up
+pub const SNAPSHOT_TIMEOUT: time::Duration = time::Duration::from_millis(10000);
 
-            let req = Request::FindSuccessorFinger { index: next, finger_id };
-            network::send_request(node_clone.to_other_node(), *node_clone.get_successor().get_ip_addr(), req);
+/// Number of imported entries that are routed through the ring at the same time
+pub const IMPORT_WINDOW_SIZE: usize = 64;
 
-            next = if next < chord::FINGERTABLE_SIZE - 1 {
-                next + 1
-            } else {
-                1
-            };
-        } else { info!("Not joined yet going to sleep again") }
+/// Time after which the dashboard is redrawn if no key is pressed
+pub const DASHBOARD_REFRESH_INTERVAL: time::Duration = time::Duration::from_millis(500);
 
-        thread::sleep(chord::NODE_FIX_FINGERS_INTERVAL);
-    }
-}
+/// Number of entries the storage log keeps for the frontends, older entries are dropped
+pub const LOG_ENTRIES_LIMIT: usize = 1000;
 
-pub fn check_predecessor(arc: Arc<Mutex<Node>>) {
-    debug!("Starting check_predecessor...");
-    loop {
-        // make a copy of node and instantly drop it
-        let node = arc.lock().unwrap();
-        let node_clone = node.clone();
-        drop(node);
-
-        if node_clone.is_joined() {
-            if let Some(predecessor) = node_clone.get_predecessor().clone() {
-                if !network::check_alive(*predecessor.get_ip_addr(), node_clone.to_other_node().clone()) {
-                    debug!("Predecessor Node #{} is dead", predecessor.get_id());
-
-                    // after async operation check_alive() lock again.
-                    arc.lock().unwrap().set_predecessor(None);
-                } else {
-                    debug!("Predecessor Node #{} is alive", predecessor.get_id());
-                }
-            }
-        } else { info!("Not joined yet going to sleep again") }
+/// Size in bytes after which a log file is deleted and started anew
+pub const LOG_FILE_SIZE_LIMIT: u64 = 2 * 1024 * 1024;
 
-        thread::sleep(chord::NODE_CHECK_PREDECESSOR_INTERVAL);
-    }
-}
+pub const NODE_CHECK_REQUESTS_INTERVAL: time::Duration = time::Duration::from_millis(250);
 
-pub fn print_and_interact(arc: Arc<Mutex<Node>>)  -> Result<(), Box<Error>> {
-    let interaction_in_progress = Arc::new(AtomicBool::new(false));
-    let i_clone = interaction_in_progress.clone();
+/// Time to wait for a response before a request is retried, doubled with every retry
+pub const REQUEST_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_millis(1000);
 
-    let node = arc.lock().unwrap();
-    let other_node = node.to_other_node().clone();
-    drop(node);
+pub const REQUEST_MAX_RETRIES: u32 = 2;
 
-    let _handle = thread::Builder::new().name("Interaction".to_string()).spawn(move || {
-        loop {
-            let buffer = &mut String::new();
-            stdin().read_line(buffer).unwrap();
-            if let "m" = buffer.trim_right() {
-                i_clone.store(true, Ordering::SeqCst);
-                perform_user_interaction(other_node.clone()).expect("perform_user_interaction failed");
-                i_clone.store(false, Ordering::SeqCst);
-            };
-        }
-    }).unwrap();
+pub const NODE_PING_INTERVAL: time::Duration = time::Duration::from_millis(500);
 
-    loop {
-        let node = arc.lock().unwrap();
-        let node_clone = node.clone();
-        drop(node);
-        if node_clone.is_joined() && !interaction_in_progress.load(Ordering::SeqCst) {
-            print::print_current_node_state(&node_clone)
-        }
-        thread::sleep(chord::NODE_PRINT_INTERVAL);
-    }
-}
+/// Time after which the owner of a key or topic drops a watch or subscription that was not renewed
+pub const LEASE_INTERVAL: time::Duration = time::Duration::from_millis(30000);
+
+/// Time after which a node renews its watches and subscriptions, well within the lease
+pub const LEASE_RENEW_INTERVAL: time::Duration = time::Duration::from_millis(10000);
+
+/// Time a deleted key is remembered, so stale copies of it are not merged back
+pub const TOMBSTONE_INTERVAL: time::Duration = time::Duration::from_millis(300_000);
+
+/// Suspicion level above which a peer is considered dead (phi = 8 means a 1 in 10^8 chance of being wrong)
+pub const PHI_THRESHOLD: f64 = 8.0;
 
-pub fn listen_for_kill_signal(arc: Arc<Mutex<Node>>) -> Result<(), Box<Error>> {
+/// Dual stack address, accepting IPv4 connections as IPv4 mapped IPv6 addresses
+pub const LISTENING_ADDRESS: IpAddr = IpAddr::V6(Ipv6Addr::UNSPECIFIED);
+
+/// Used instead of `LISTENING_ADDRESS` if the host does not support IPv6
+pub const LISTENING_ADDRESS_V4: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+
+/// Maximum size of a single inbound message in bytes
+pub const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Time a peer has to send its complete message after connecting
+pub const READ_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_millis(5000);
+
+pub const MAX_INBOUND_CONNECTIONS: usize = 256;
+
+/// Messages per second a single peer ip is allowed to send on average
+pub const PEER_RATE_LIMIT: f64 = 500.0;
+
+/// Messages a single peer ip is allowed to send in a burst
+pub const PEER_RATE_LIMIT_BURST: f64 = 1000.0;
+
+/// Peer ip addresses the rate limit keeps a bucket for, the least recently seen one is forgotten first
+pub const MAX_RATE_LIMITED_PEERS: usize = 1024;
+
+/// Multicast group on which nodes announce themselves for LAN discovery
+pub const DISCOVERY_MULTICAST_ADDR: Ipv4Addr = Ipv4Addr::new(239, 255, 42, 99);
+
+pub const DISCOVERY_PORT: u16 = 34200;
+
+pub const DISCOVERY_ANNOUNCE_INTERVAL: time::Duration = time::Duration::from_millis(1000);
+
+/// Time a new node listens for announcements before it creates a new ring
+pub const DISCOVERY_TIMEOUT_INTERVAL: time::Duration = time::Duration::from_millis(3000);
+
+pub const MAX_ANNOUNCEMENT_SIZE: usize = 4096;
+
+pub const DEFAULT_CLUSTER_NAME: &str = "hll-chord";
+
+pub fn listen_for_kill_signal(actor: Sender<Command>) -> Result<(), ChordError> {
     let signals = Signals::new(&[SIGINT])?;
     let _handle = thread::Builder::new().name("Interaction".to_string()).spawn(move || {
         for sig in signals.forever() {
-            if sig == SIGINT {
-                let node = arc.lock().unwrap();
-                let node_clone = node.clone();
-                drop(node);
-
-                let handle_opt = node_clone.clone().graceful_shutdown();
-                if let Some(handle) = handle_opt {
-                    handle.join().expect("handle_graceful_shutdown failed");
-                }
+            if sig == SIGINT && actor.send(Command::Shutdown).is_err() {
                 process::exit(0);
             }
         }
-    }).unwrap();
+    })?;
 
     Ok(())
 }
 
 pub fn create_node_id(ip_addr: SocketAddr) -> BigInt {
-    let hash = create_hash(&ip_addr.to_string());
+    let hash = create_hash(&canonical_addr(ip_addr).to_string());
     let byte_vec = hash.as_bytes().to_vec();
     let id = BigInt::from_bytes_be(Sign::Plus, &byte_vec);
     x_modulo_ring_size(id)
 }
 
+/// Alternative node id, if the id of the address is already taken by another node
+pub fn create_salted_node_id(ip_addr: SocketAddr, salt: u32) -> BigInt {
+    create_id(&format!("{}#{}", canonical_addr(ip_addr), salt))
+}
+
+/// Address in the form used for node ids, so a node gets the same id no matter how its address
+/// was written. IPv4 addresses stay `a.b.c.d:port`, IPv6 addresses become `[ip%scope]:port`.
+/// The scope id is kept, a link-local address can't be reached without it.
+pub fn canonical_addr(addr: SocketAddr) -> SocketAddr {
+    match addr {
+        SocketAddr::V6(addr6) => match canonical_ip(IpAddr::V6(*addr6.ip())) {
+            IpAddr::V4(ip4) => SocketAddr::new(IpAddr::V4(ip4), addr6.port()),
+            IpAddr::V6(ip6) => SocketAddr::V6(SocketAddrV6::new(ip6, addr6.port(), 0, addr6.scope_id())),
+        },
+        addr4 => addr4,
+    }
+}
+
+/// Unwraps IPv4 mapped IPv6 addresses, as seen by dual stack listeners
+pub fn canonical_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(ip6) => match ip6.segments() {
+            [0, 0, 0, 0, 0, 0xffff, _, _] => IpAddr::V4(ip6.to_ipv4().unwrap()),
+            _ => IpAddr::V6(ip6),
+        },
+        ip4 => ip4,
+    }
+}
+
 pub fn create_id(string: &str) -> BigInt {
     let hash = create_hash(string);
     let byte_vec = hash.as_bytes().to_vec();
@@ -244,8 +232,122 @@ fn x_modulo_ring_size(x: BigInt) -> BigInt {
     BigInt::modpow(&x, one, chord_ring_size)
 }
 
-pub fn spawn_node(node_ip_addr: SocketAddr, port: i32, entry_node_addr: Option<SocketAddr>) -> JoinHandle<()> {
-    if entry_node_addr.is_some() {
+/// Checks that a seed node is given as `HOST:PORT`, with an IPv6 host in brackets. Hostnames are
+/// only resolved when joining, so a seed that is down in DNS right now is no error.
+pub fn validate_seed(seed: &str) -> Result<(), ChordError> {
+    if seed.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+    let (host, port) = match seed.rfind(':') {
+        Some(index) => (&seed[..index], &seed[index + 1..]),
+        None => return Err(ChordError::InvalidArgument(format!("HOST:PORT '{}': missing port", seed))),
+    };
+    if host.is_empty() || host.contains(':') || host.starts_with('[') {
+        return Err(ChordError::InvalidArgument(format!(
+            "HOST:PORT '{}': invalid host, IPv6 addresses are written in brackets, e.g. [::1]:{}", seed, port)));
+    }
+    port.parse::<u16>()
+        .map_err(|e| ChordError::InvalidArgument(format!("HOST:PORT '{}': port {}", seed, e)))?;
+    Ok(())
+}
+
+/// Resolves a seed node given as `HOST:PORT`, where the host is an ip address or a DNS hostname
+pub fn resolve_seed(seed: &str) -> Result<Vec<SocketAddr>, ChordError> {
+    let addrs = seed.to_socket_addrs()
+        .map_err(|e| ChordError::InvalidAddress(format!("{}: {}", seed, e)))?;
+    Ok(addrs.collect())
+}
+
+/// Tries the seed nodes in order until the node has joined, backing off after every round.
+/// Seeds are resolved again in every round, so hostnames may change while the node waits.
+fn join_via_seeds(actor: &Sender<Command>, node_ip_addr: SocketAddr, seeds: &[String], join_timeout: time::Duration) -> Result<(), ChordError> {
+    let deadline = Instant::now() + join_timeout;
+    let mut backoff = chord::NODE_INIT_SLEEP_INTERVAL;
+    loop {
+        for seed in seeds {
+            let entry_points = match chord::resolve_seed(seed) {
+                Ok(entry_points) => entry_points,
+                Err(e) => {
+                    warn!("Skipping seed node, {}", e);
+                    continue;
+                }
+            };
+            for entry_point in entry_points.into_iter().filter(|addr| *addr != node_ip_addr) {
+                if Instant::now() >= deadline {
+                    return Err(ChordError::JoinTimeout(seeds.join(", ")));
+                }
+                let (reply, joined) = mpsc::channel();
+                actor.send(Command::Join { entry_point, reply }).map_err(|_| actor_stopped())?;
+                if joined.recv().map_err(|_| actor_stopped())? {
+                    return Ok(());
+                }
+                let attempt_deadline = cmp::min(deadline, Instant::now() + chord::JOIN_ATTEMPT_TIMEOUT);
+                if wait_for_join(actor, attempt_deadline)? {
+                    return Ok(());
+                }
+                debug!("Entry point {} did not let the node join in time", entry_point);
+            }
+        }
+        if Instant::now() + backoff >= deadline {
+            return Err(ChordError::JoinTimeout(seeds.join(", ")));
+        }
+        info!("No seed node answered, trying again in {:?}", backoff);
+        thread::sleep(backoff);
+        backoff = cmp::min(backoff * 2, chord::JOIN_MAX_BACKOFF_INTERVAL);
+    }
+}
+
+/// Polls the state of the node until it has joined, returns false if the deadline passed before
+fn wait_for_join(actor: &Sender<Command>, deadline: Instant) -> Result<bool, ChordError> {
+    loop {
+        let (reply, state) = mpsc::channel();
+        actor.send(Command::GetState { reply }).map_err(|_| actor_stopped())?;
+        if state.recv().map_err(|_| actor_stopped())?.is_member() {
+            return Ok(true);
+        }
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        thread::sleep(chord::JOIN_POLL_INTERVAL);
+    }
+}
+
+fn actor_stopped() -> ChordError {
+    ChordError::Io("node actor stopped".to_string())
+}
+
+/// Settings of a node besides its addresses
+pub struct NodeOptions {
+    /// Seed nodes to join, a new ring is created if there are none
+    pub seeds: Vec<String>,
+    pub join_timeout: time::Duration,
+    pub salt_id: bool,
+    /// Cluster name to announce the node for LAN discovery
+    pub cluster: Option<String>,
+    /// Bound admin socket to control the node
+    pub admin: Option<net::TcpListener>,
+    pub frontend: Frontend,
+    /// Called after joining if the node joined with a salted id, e.g. to name the log files by it
+    pub on_id_changed: Box<dyn FnOnce(&BigInt) + Send>,
+}
+
+/// How the node is operated by the user
+#[derive(Clone)]
+pub enum Frontend {
+    /// Interactive shell on the terminal
+    Shell,
+    /// Full screen dashboard on the terminal
+    Dashboard,
+    /// Commands read from the file (stdin for `-`), answered with a transcript on stdout
+    Script(String),
+    /// No interaction at all, the node is controlled through its admin socket
+    Daemon,
+}
+
+/// Spawns a node on the bound listener, which is known to other nodes by its advertised address
+pub fn spawn_node(listener: net::TcpListener, node_ip_addr: SocketAddr, options: NodeOptions) -> JoinHandle<()> {
+    let NodeOptions { seeds, join_timeout, salt_id, cluster, admin, frontend, on_id_changed } = options;
+    if !seeds.is_empty() {
         info!("Spawn node and join.");
     } else {
         info!("Spawn master node.");
@@ -253,70 +355,169 @@ pub fn spawn_node(node_ip_addr: SocketAddr, port: i32, entry_node_addr: Option<S
     let builder = thread::Builder::new().name("Node".to_string());
     builder
         .spawn(move || {
-            let node = if entry_node_addr.is_some() {
-                Node::new(node_ip_addr)
+            let node = if !seeds.is_empty() {
+                // Resolvable seeds are remembered, in case the node has to rejoin later
+                let bootstrap_peers = seeds.iter()
+                    .filter_map(|seed| chord::resolve_seed(seed).ok())
+                    .flatten()
+                    .filter(|addr| *addr != node_ip_addr)
+                    .collect();
+                Node::new(node_ip_addr, bootstrap_peers, salt_id)
             } else {
                 Node::new_first(node_ip_addr)
             };
             // let mut node = node::Node::new(node_ip_addr.clone());
             let id = node.get_id().clone();
-            let id_clone = id.clone();
+            let traffic_stats = node.get_traffic_stats().clone();
 
-            let other_node = node.to_other_node();
-
-            let arc = Arc::new(Mutex::new(node));
+            let (actor, commands) = mpsc::channel();
+            let actor_handle = thread::Builder::new().name("Actor".to_string())
+                .spawn(move || {
+                    actor::run(node, commands);
+                }).unwrap();
 
-            let arc_clone1 = arc.clone();
+            let actor_clone1 = actor.clone();
             let handle1 = thread::Builder::new().name("Listen".to_string())
                 .spawn(move || {
-                    network::start_listening_on_socket(arc_clone1, port, id_clone).expect("network_util::start_listening_on_socket failed");
+                    if let Err(e) = network::start_listening_on_socket(listener, actor_clone1, traffic_stats) {
+                        error!("Unable to listen: {}", e);
+                        process::exit(1);
+                    }
                 }).unwrap();
 
-            let arc_join = arc.clone();
-            if let Some(entry_node_addr) = entry_node_addr {
+            if !seeds.is_empty() {
                 thread::sleep(chord::NODE_INIT_SLEEP_INTERVAL);
-                let mut node_join = arc_join.lock().unwrap();
-                while !node_join.is_joined() {
-                    drop(node_join);
-                    chord::join(id.clone(), other_node.clone(), entry_node_addr);
-                    thread::sleep(chord::NODE_INIT_SLEEP_INTERVAL);
-                    node_join = arc_join.lock().unwrap();
+                if let Err(e) = join_via_seeds(&actor, node_ip_addr, &seeds, join_timeout) {
+                    error!("{}", e);
+                    process::exit(1);
                 }
-
             }
 
-            let arc_clone2 = arc.clone();
-            let handle2 = thread::Builder::new().name("Stabilize".to_string())
-                .spawn(move || {
-                    chord::stabilize(arc_clone2);
-                }).unwrap();
-
-            let arc_clone3 = arc.clone();
-            let handle3 = thread::Builder::new().name("Fix_Fingers".to_string())
-                .spawn(move || {
-                    chord::fix_fingers(arc_clone3);
-                }).unwrap();
-
-            let arc_clone4 = arc.clone();
-            let handle4 = thread::Builder::new().name("Check_Predecessor".to_string())
-                .spawn(move || {
-                    chord::check_predecessor(arc_clone4);
-                }).unwrap();
+            // The id of the node is final once it has joined, it is salted if the original id was taken
+            let (reply, joined_node) = mpsc::channel();
+            let other_node = match actor.send(Command::GetNode { reply }).ok().and_then(|_| joined_node.recv().ok()) {
+                Some(other_node) => other_node,
+                None => {
+                    error!("Node actor stopped while joining");
+                    process::exit(1);
+                }
+            };
+            if other_node.get_id() != &id {
+                info!("Joined with salted id #{} instead of #{}", other_node.get_id(), id);
+                on_id_changed(other_node.get_id());
+            }
 
-            let arc_clone5 = arc.clone();
-            let handle5 = thread::Builder::new().name("Print_Interact".to_string())
-                .spawn(move || {
-                    chord::print_and_interact(arc_clone5).expect("print_and_interact failed");
-                }).unwrap();
+            let handle2 = actor::spawn_timer("Stabilize", actor.clone(), chord::NODE_STABILIZE_INTERVAL, || Command::Stabilize);
+            let handle3 = actor::spawn_timer("Fix_Fingers", actor.clone(), chord::NODE_FIX_FINGERS_INTERVAL, || Command::FixFingers);
+            let handle4 = actor::spawn_timer("Check_Predecessor", actor.clone(), chord::NODE_CHECK_PREDECESSOR_INTERVAL, || Command::CheckPredecessor);
+            let handle5 = actor::spawn_timer("Expire_Keys", actor.clone(), chord::NODE_EXPIRE_KEYS_INTERVAL, || Command::ExpireKeys);
+            let handle6 = actor::spawn_timer("Check_Requests", actor.clone(), chord::NODE_CHECK_REQUESTS_INTERVAL, || Command::CheckRequests);
+            let handle7 = actor::spawn_timer("Ping", actor.clone(), chord::NODE_PING_INTERVAL, || Command::PingPeers);
+            let handle11 = actor::spawn_timer("Renew_Leases", actor.clone(), chord::LEASE_RENEW_INTERVAL, || Command::RenewLeases);
+
+            let actor_clone8 = actor.clone();
+            let other_node_clone8 = other_node.clone();
+            let handle8 = match frontend {
+                Frontend::Shell => Some(thread::Builder::new().name("Interact".to_string())
+                    .spawn(move || {
+                        if let Err(e) = run_repl(actor_clone8, other_node_clone8) {
+                            error!("{}", e);
+                        }
+                    }).unwrap()),
+                Frontend::Dashboard => Some(thread::Builder::new().name("Dashboard".to_string())
+                    .spawn(move || {
+                        if let Err(e) = dashboard::run(actor_clone8, other_node_clone8) {
+                            error!("{}", e);
+                        }
+                    }).unwrap()),
+                Frontend::Script(path) => Some(thread::Builder::new().name("Script".to_string())
+                    .spawn(move || {
+                        if let Err(e) = script::run(&path, &actor_clone8) {
+                            error!("Unable to run script {}: {}", path, e);
+                        }
+                        // The scenario is over, hand over the keys and terminate
+                        let _ = actor_clone8.send(Command::Shutdown);
+                    }).unwrap()),
+                Frontend::Daemon => None,
+            };
 
-            let arc_clone6 = arc.clone();
-            chord::listen_for_kill_signal(arc_clone6).expect("listen_for_kill_signal failed");
+            let handle9 = cluster.map(|cluster| {
+                let actor_clone9 = actor.clone();
+                let other_node_clone9 = other_node.clone();
+                thread::Builder::new().name("Announce".to_string())
+                    .spawn(move || {
+                        if let Err(e) = discovery::announce(actor_clone9, cluster, other_node_clone9) {
+                            error!("Unable to announce node for discovery: {}", e);
+                        }
+                    }).unwrap()
+            });
+
+            let handle10 = admin.map(|admin| {
+                let actor_clone10 = actor.clone();
+                thread::Builder::new().name("Admin".to_string())
+                    .spawn(move || {
+                        if let Err(e) = admin::serve(admin, actor_clone10, other_node) {
+                            error!("Admin socket failed: {}", e);
+                        }
+                    }).unwrap()
+            });
+
+            if let Err(e) = chord::listen_for_kill_signal(actor) {
+                error!("Unable to listen for kill signal: {}", e);
+            }
 
+            actor_handle.join().expect("actor_handle failed");
             handle1.join().expect("handle1 failed");
             handle2.join().expect("handle2 failed");
             handle3.join().expect("handle3 failed");
             handle4.join().expect("handle4 failed");
             handle5.join().expect("handle5 failed");
+            handle6.join().expect("handle6 failed");
+            handle7.join().expect("handle7 failed");
+            handle11.join().expect("handle11 failed");
+            if let Some(handle8) = handle8 {
+                handle8.join().expect("handle8 failed");
+            }
+            if let Some(handle9) = handle9 {
+                handle9.join().expect("handle9 failed");
+            }
+            if let Some(handle10) = handle10 {
+                handle10.join().expect("handle10 failed");
+            }
         })
         .unwrap()
 }
+
+/// Wall clock time used to measure round trip times of pings
+pub fn now_as_millis() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use super::{canonical_addr, validate_seed};
+
+    #[test]
+    fn canonical_addr_unwraps_mapped_ipv4_and_keeps_scope_id() {
+        let mapped: SocketAddr = "[::ffff:127.0.0.1]:4000".parse().unwrap();
+        assert_eq!(canonical_addr(mapped), "127.0.0.1:4000".parse::<SocketAddr>().unwrap());
+        let link_local: SocketAddr = "[fe80::1%2]:4000".parse().unwrap();
+        assert_eq!(canonical_addr(link_local).to_string(), "[fe80::1%2]:4000");
+    }
+
+    #[test]
+    fn seeds_need_a_port_and_brackets_around_ipv6() {
+        assert!(validate_seed("127.0.0.1:4000").is_ok());
+        assert!(validate_seed("[::1]:4000").is_ok());
+        assert!(validate_seed("[fe80::1%2]:4000").is_ok());
+        assert!(validate_seed("seed.example.org:4000").is_ok());
+        assert!(validate_seed("seed.example.org").is_err());
+        assert!(validate_seed("::1").is_err());
+        assert!(validate_seed("fe80::1:4000").is_err());
+        assert!(validate_seed("[::1]").is_err());
+        assert!(validate_seed("seed.example.org:port").is_err());
+    }
+}
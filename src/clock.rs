@@ -0,0 +1,64 @@
+use std::time::Instant;
+
+use super::chord;
+
+/// Source of the current time for the node logic, so timeouts and failure
+/// detection can be driven by a manual clock in tests
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+
+    /// Wall clock time used to measure round trip times of pings
+    fn now_as_millis(&self) -> u64;
+}
+
+/// Clock of the operating system, used by running nodes
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn now_as_millis(&self) -> u64 {
+        chord::now_as_millis()
+    }
+}
+
+#[cfg(test)]
+pub use self::manual::ManualClock;
+
+#[cfg(test)]
+mod manual {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::Clock;
+
+    /// Clock that only moves when it is advanced, clones share the same time
+    #[derive(Clone)]
+    pub struct ManualClock {
+        start: Instant,
+        elapsed: Arc<Mutex<Duration>>,
+    }
+
+    impl ManualClock {
+        pub fn new() -> ManualClock {
+            ManualClock { start: Instant::now(), elapsed: Arc::new(Mutex::new(Duration::from_secs(0))) }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.elapsed.lock().unwrap() += duration;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + *self.elapsed.lock().unwrap()
+        }
+
+        fn now_as_millis(&self) -> u64 {
+            let elapsed = *self.elapsed.lock().unwrap();
+            elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis())
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use std::cmp;
+use std::io::{stdin, stdout, Write};
+use std::sync::mpsc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread;
+use std::time::Duration;
+
+use termion::{clear, color, cursor, style};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::AlternateScreen;
+
+use super::actor::Command;
+use super::chord;
+use super::error::ChordError;
+use super::input;
+use super::node::{Node, OtherNode};
+
+const MIN_WIDTH: u16 = 80;
+
+const MIN_HEIGHT: u16 = 22;
+
+/// Height of the node information pane, including its borders
+const NODE_INFO_HEIGHT: u16 = 9;
+
+const HINTS: &str = "p put  g get  d delete  tab switch pane  \u{2191}\u{2193} PgUp PgDn Home End scroll  q quit";
+
+/// Rows of a pane the dashboard shows, only these are copied into a snapshot
+#[derive(Clone, Copy)]
+pub struct Window {
+    offset: usize,
+    rows: usize,
+}
+
+/// Lines of a pane starting at `offset`, out of `total` lines
+struct Page {
+    offset: usize,
+    lines: Vec<String>,
+    total: usize,
+}
+
+impl Page {
+    fn all(lines: Vec<String>) -> Page {
+        Page { offset: 0, total: lines.len(), lines }
+    }
+
+    /// Formats the lines in the window only, the offset is clamped to the last page like when drawing
+    fn window<T, I, F>(items: I, total: usize, window: Window, format: F) -> Page
+        where I: Iterator<Item = T>, F: Fn(T) -> String {
+        let offset = cmp::min(window.offset, total.saturating_sub(window.rows));
+        let lines = items.skip(offset).take(window.rows).map(format).collect();
+        Page { offset, lines, total }
+    }
+}
+
+/// Copy of the node state rendered by the dashboard, taken by the node actor
+pub struct Snapshot {
+    title: String,
+    node_info: Page,
+    successors: Page,
+    fingers: Page,
+    storage: Page,
+    log: Page,
+}
+
+impl Snapshot {
+    pub fn new(node: &Node, storage_window: Window, log_window: Window) -> Snapshot {
+        let predecessor = match node.get_predecessor() {
+            Some(pre) => format!("{:<12}{:>9}  {}", "Predecessor", pre.get_id(), pre.get_ip_addr()),
+            None => format!("{:<12}{:>9}  {}", "Predecessor", "None", "None"),
+        };
+        let successor = node.get_successor();
+        let rejected_traffic: Vec<String> = node.get_traffic_stats().get_all().iter()
+            .map(|(rejection, count)| format!("{}: {}", rejection, count))
+            .collect();
+        let node_info = vec![
+            predecessor,
+            format!("{}{:<12}{:>9}  {}{}", color::Fg(color::Green), "Self", node.get_id(), node.get_ip_addr(), color::Fg(color::Reset)),
+            format!("{:<12}{:>9}  {}", "Successor", successor.get_id(), successor.get_ip_addr()),
+            format!("{:<12}{}", "State", node.get_state()),
+            format!("{:<12}{}", "Keys", node.get_storage().get_data_as_iter().count()),
+            format!("{:<12}{}", "Rejected", rejected_traffic.join(", ")),
+        ];
+
+        let successors = node.get_successor_list().iter().enumerate()
+            .map(|(i, succ)| {
+                let rtt_string = match node.get_failure_detector().get_rtt(succ.get_id()) {
+                    Some(rtt) => format!("{}ms", rtt.as_secs() * 1000 + u64::from(rtt.subsec_millis())),
+                    None => "-".to_string(),
+                };
+                format!("{:>2}  {:>9}  {:<26}{:>6}", i, succ.get_id(), succ.get_ip_addr(), rtt_string)
+            })
+            .collect();
+
+        let fingers = (0..node.get_finger_table().length())
+            .map(|i| {
+                let entry = node.get_finger_table().get(i);
+                format!("{:>2}  {:>9}  {:>9}", i, entry.get_id(), entry.get_node().get_id())
+            })
+            .collect();
+
+        // Only the ids are sorted, the entries are copied for the visible rows
+        let mut entries: Vec<_> = node.get_storage().get_data_as_iter()
+            .filter(|(_, dht_entry)| !dht_entry.is_expired())
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+        let storage = Page::window(entries.iter(), entries.len(), storage_window, |(key_id, dht_entry)| {
+            let ttl_string = match dht_entry.get_remaining_ttl() {
+                Some(ttl) => format!("{}s", ttl.as_secs()),
+                None => "-".to_string(),
+            };
+            format!("{:>9}  {:>4}  {:>6}  {:<16}  {}", key_id, dht_entry.get_version(), ttl_string,
+                    dht_entry.get_key(), dht_entry.get_value())
+        });
+
+        // Newest entry first, so the log pane shows the latest operations without scrolling
+        let logs = node.get_storage().get_log_entries();
+        let log = Page::window(logs.iter().rev(), logs.len(), log_window, |entry| entry.to_string());
+
+        Snapshot {
+            title: format!("Node #{} {} ({})", node.get_id(), node.get_ip_addr(), node.get_state()),
+            node_info: Page::all(node_info),
+            successors: Page::all(successors),
+            fingers: Page::all(fingers),
+            storage,
+            log,
+        }
+    }
+}
+
+/// Scrollable panes, in the order they are focused with the tab key
+#[derive(Clone, Copy, PartialEq)]
+enum Pane {
+    Successors,
+    Fingers,
+    Storage,
+    Log,
+}
+
+const PANES: [Pane; 4] = [Pane::Successors, Pane::Fingers, Pane::Storage, Pane::Log];
+
+#[derive(Clone, Copy)]
+enum Operation {
+    Put,
+    Get,
+    Delete,
+}
+
+impl Operation {
+    fn get_name(self) -> &'static str {
+        match self {
+            Operation::Put => "Put",
+            Operation::Get => "Get",
+            Operation::Delete => "Delete",
+        }
+    }
+
+    fn get_fields(self) -> &'static [&'static str] {
+        match self {
+            Operation::Put => &["key", "value", "ttl in seconds (empty: forever)"],
+            Operation::Get | Operation::Delete => &["key"],
+        }
+    }
+}
+
+/// Input line asking for the fields of a DHT operation one after another
+struct Prompt {
+    operation: Operation,
+    values: Vec<String>,
+    buffer: String,
+}
+
+#[derive(Clone, Copy)]
+struct Area {
+    x: u16,
+    y: u16,
+    width: u16,
+    height: u16,
+}
+
+struct Dashboard {
+    node: OtherNode,
+    snapshot: Option<Snapshot>,
+    focus: usize,
+    scroll: [usize; 4],
+    /// Number of visible rows of every pane as last drawn, used for paging
+    rows: [usize; 4],
+    prompt: Option<Prompt>,
+    message: Option<String>,
+    size: (u16, u16),
+}
+
+/// Runs the full screen dashboard until the user quits, then shuts the node down
+pub fn run(actor: Sender<Command>, node: OtherNode) -> Result<(), ChordError> {
+    let (key_sender, keys) = mpsc::channel();
+    thread::Builder::new().name("Dashboard_Keys".to_string()).spawn(move || {
+        for key in stdin().keys() {
+            match key {
+                Ok(key) => {
+                    if key_sender.send(key).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Unable to read from stdin: {}", e);
+                    break;
+                }
+            }
+        }
+    })?;
+
+    let mut screen = AlternateScreen::from(stdout().into_raw_mode()?);
+    write!(screen, "{}", cursor::Hide)?;
+    let mut dashboard = Dashboard::new(node);
+    let quit = loop {
+        let (storage, log) = dashboard.windows()?;
+        let (reply, snapshot) = mpsc::channel();
+        if actor.send(Command::GetSnapshot { storage, log, reply }).is_err() {
+            break false;
+        }
+        if let Ok(snapshot) = snapshot.recv() {
+            dashboard.snapshot = Some(snapshot);
+        }
+        dashboard.draw(&mut screen)?;
+
+        match keys.recv_timeout(chord::DASHBOARD_REFRESH_INTERVAL) {
+            Ok(key) => {
+                if !dashboard.handle_key(key) {
+                    break true;
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break false,
+        }
+    };
+    write!(screen, "{}{}", style::Reset, cursor::Show)?;
+    screen.flush()?;
+    // Restore the terminal before the node hands over its keys and exits
+    drop(screen);
+
+    if quit {
+        actor.send(Command::Shutdown).map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+    }
+    Ok(())
+}
+
+impl Dashboard {
+    fn new(node: OtherNode) -> Dashboard {
+        Dashboard {
+            node,
+            snapshot: None,
+            focus: 0,
+            scroll: [0; 4],
+            rows: [0; 4],
+            prompt: None,
+            message: None,
+            size: (0, 0),
+        }
+    }
+
+    /// Windows of the storage and log panes at their scroll offsets. No pane has more rows than the
+    /// terminal, so the window covers the visible rows even after the terminal was resized.
+    fn windows(&self) -> Result<(Window, Window), ChordError> {
+        let (_, height) = termion::terminal_size()?;
+        let rows = height as usize;
+        Ok((Window { offset: self.scroll[Pane::Storage as usize], rows }, Window { offset: self.scroll[Pane::Log as usize], rows }))
+    }
+
+    /// Handles a key press, returns false if the user wants to quit
+    fn handle_key(&mut self, key: Key) -> bool {
+        if let Some(mut prompt) = self.prompt.take() {
+            match key {
+                Key::Esc => self.message = Some(format!("{} cancelled", prompt.operation.get_name())),
+                Key::Char('\n') => {
+                    let field = prompt.operation.get_fields()[prompt.values.len()];
+                    let value = prompt.buffer.trim().to_string();
+                    if value.is_empty() && !field.starts_with("ttl") {
+                        self.message = Some(format!("Please enter a valid {}", field));
+                        self.prompt = Some(prompt);
+                    } else {
+                        prompt.values.push(value);
+                        prompt.buffer.clear();
+                        if prompt.values.len() == prompt.operation.get_fields().len() {
+                            self.message = Some(self.execute(prompt.operation, prompt.values));
+                        } else {
+                            self.message = None;
+                            self.prompt = Some(prompt);
+                        }
+                    }
+                }
+                Key::Backspace => {
+                    prompt.buffer.pop();
+                    self.prompt = Some(prompt);
+                }
+                Key::Char(c) if !c.is_control() => {
+                    prompt.buffer.push(c);
+                    self.prompt = Some(prompt);
+                }
+                _ => self.prompt = Some(prompt),
+            }
+            return true;
+        }
+
+        let rows = cmp::max(self.rows[self.focus], 1);
+        match key {
+            Key::Char('q') | Key::Ctrl('c') => return false,
+            Key::Char('p') => self.open_prompt(Operation::Put),
+            Key::Char('g') => self.open_prompt(Operation::Get),
+            Key::Char('d') => self.open_prompt(Operation::Delete),
+            Key::Char('\t') => self.focus = (self.focus + 1) % PANES.len(),
+            Key::Up | Key::Char('k') => self.scroll_by(-1),
+            Key::Down | Key::Char('j') => self.scroll_by(1),
+            Key::PageUp => self.scroll_by(-(rows as isize)),
+            Key::PageDown => self.scroll_by(rows as isize),
+            Key::Home => self.scroll[self.focus] = 0,
+            // Clamped to the last page when drawing
+            Key::End => self.scroll[self.focus] = usize::MAX,
+            _ => {}
+        }
+        true
+    }
+
+    fn open_prompt(&mut self, operation: Operation) {
+        self.message = None;
+        self.prompt = Some(Prompt { operation, values: Vec::new(), buffer: String::new() });
+    }
+
+    fn scroll_by(&mut self, delta: isize) {
+        let scroll = &mut self.scroll[self.focus];
+        if delta < 0 {
+            *scroll = scroll.saturating_sub((-delta) as usize);
+        } else {
+            *scroll = scroll.saturating_add(delta as usize);
+        }
+    }
+
+    /// Sends the DHT request, its result shows up in the operation log
+    fn execute(&self, operation: Operation, mut values: Vec<String>) -> String {
+        let key = values.remove(0);
+        match operation {
+            Operation::Put => {
+                let value = values.remove(0);
+                let ttl = match values[0].parse::<u64>() {
+                    Ok(secs) => Some(Duration::from_secs(secs)),
+                    Err(_) if values[0].is_empty() => None,
+                    Err(_) => return format!("'{}' is no valid number of seconds", values[0]),
+                };
+                input::store_key_value(key.clone(), value, ttl, self.node.clone());
+                format!("Storing key '{}', the result shows up in the operation log", key)
+            }
+            Operation::Get => {
+                input::find_key(key.clone(), self.node.clone());
+                format!("Looking for key '{}', the result shows up in the operation log", key)
+            }
+            Operation::Delete => {
+                input::delete_key(key.clone(), self.node.clone());
+                format!("Deleting key '{}', the result shows up in the operation log", key)
+            }
+        }
+    }
+
+    fn draw<W: Write>(&mut self, out: &mut W) -> Result<(), ChordError> {
+        let (width, height) = termion::terminal_size()?;
+        let mut frame = String::new();
+        if (width, height) != self.size {
+            frame.push_str(clear::All.as_ref());
+            self.size = (width, height);
+        }
+
+        if width < MIN_WIDTH || height < MIN_HEIGHT {
+            frame.push_str(&format!("{}{}{}", cursor::Goto(1, 1), clear::All,
+                                    fit(&format!("Terminal too small, at least {}x{} needed", MIN_WIDTH, MIN_HEIGHT), width as usize)));
+            write!(out, "{}", frame)?;
+            out.flush()?;
+            return Ok(());
+        }
+
+        let snapshot = match &self.snapshot {
+            Some(snapshot) => snapshot,
+            None => return Ok(()),
+        };
+
+        // Title bar
+        frame.push_str(&format!("{}{}{}{}{}", cursor::Goto(1, 1), style::Invert, style::Bold,
+                                fit(&format!(" {}", snapshot.title), width as usize), style::Reset));
+
+        // Node information and successor list on the left, finger table in the middle, storage on the right,
+        // the operation log spans the whole width below
+        let log_height = cmp::max(6, (height - 2) / 3);
+        let top_height = height - 2 - log_height;
+        let left_width = width * 2 / 5;
+        let middle_width = width / 5;
+        let right_width = width - left_width - middle_width;
+
+        let node_info = Area { x: 1, y: 2, width: left_width, height: NODE_INFO_HEIGHT };
+        draw_pane(&mut frame, node_info, "Node information", &format!("{:<12}{:>9}  {}", "Descr.", "ID", "SocketAddr"),
+                  &snapshot.node_info, 0, false);
+
+        let areas = [
+            Area { x: 1, y: 2 + NODE_INFO_HEIGHT, width: left_width, height: top_height - NODE_INFO_HEIGHT },
+            Area { x: 1 + left_width, y: 2, width: middle_width, height: top_height },
+            Area { x: 1 + left_width + middle_width, y: 2, width: right_width, height: top_height },
+            Area { x: 1, y: 2 + top_height, width, height: log_height },
+        ];
+        for (i, pane) in PANES.iter().enumerate() {
+            let (title, header, lines) = match pane {
+                Pane::Successors => ("Successor list", format!("{:>2}  {:>9}  {:<26}{:>6}", "#", "ID", "SocketAddr", "RTT"),
+                                     &snapshot.successors),
+                Pane::Fingers => ("Finger table", format!("{:>2}  {:>9}  {:>9}", "#", "Finger", "Node"), &snapshot.fingers),
+                Pane::Storage => ("Storage", format!("{:>9}  {:>4}  {:>6}  {:<16}  {}", "ID", "Ver.", "TTL", "Key", "Value"),
+                                  &snapshot.storage),
+                Pane::Log => ("Operation log", String::new(), &snapshot.log),
+            };
+            let (scroll, rows) = draw_pane(&mut frame, areas[i], title, &header, lines, self.scroll[i], i == self.focus);
+            self.scroll[i] = scroll;
+            self.rows[i] = rows;
+        }
+
+        // Prompt, last message or key hints
+        let status = match &self.prompt {
+            Some(prompt) => {
+                let field = prompt.operation.get_fields()[prompt.values.len()];
+                let hint = match &self.message {
+                    Some(message) => format!("  ({})", message),
+                    None => String::new(),
+                };
+                format!(" {} > {}: {}_{}", prompt.operation.get_name(), field, prompt.buffer, hint)
+            }
+            None => match &self.message {
+                Some(message) => format!(" {}  |  {}", message, HINTS),
+                None => format!(" {}", HINTS),
+            },
+        };
+        frame.push_str(&format!("{}{}{}{}", cursor::Goto(1, height), style::Invert, fit(&status, width as usize), style::Reset));
+
+        write!(out, "{}", frame)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+/// Draws a bordered pane with an optional column header, returns the clamped scroll offset and the visible rows
+fn draw_pane(frame: &mut String, area: Area, title: &str, header: &str, page: &Page, scroll: usize, focused: bool) -> (usize, usize) {
+    let inner_width = area.width.saturating_sub(2) as usize;
+    let inner_height = area.height.saturating_sub(2) as usize;
+    let rows = if header.is_empty() { inner_height } else { inner_height.saturating_sub(1) };
+    let scroll = cmp::min(scroll, page.total.saturating_sub(rows));
+
+    let border = if focused {
+        format!("{}{}", style::Bold, color::Fg(color::Green))
+    } else {
+        String::new()
+    };
+    let title = fit(&format!(" {} ", title), cmp::min(title.len() + 2, inner_width.saturating_sub(1)));
+    frame.push_str(&format!("{}{}\u{250c}\u{2500}{}{}\u{2510}{}", cursor::Goto(area.x, area.y), border, title,
+                            "\u{2500}".repeat(inner_width.saturating_sub(title.chars().count() + 1)), style::Reset));
+
+    let mut y = area.y + 1;
+    if !header.is_empty() {
+        frame.push_str(&format!("{}{}\u{2502}{}{}{}{}{}{}\u{2502}{}", cursor::Goto(area.x, y), border, style::Reset,
+                                style::Italic, color::Fg(color::Yellow), fit(header, inner_width), style::Reset, border, style::Reset));
+        y += 1;
+    }
+    for i in 0..rows {
+        let line = (scroll + i).checked_sub(page.offset)
+            .and_then(|index| page.lines.get(index))
+            .map(|line| line.as_str())
+            .unwrap_or("");
+        frame.push_str(&format!("{}{}\u{2502}{}{}{}\u{2502}{}", cursor::Goto(area.x, y), border, style::Reset,
+                                fit(line, inner_width), border, style::Reset));
+        y += 1;
+    }
+
+    let position = if page.total > rows {
+        format!(" {}-{}/{} ", scroll + 1, cmp::min(scroll + rows, page.total), page.total)
+    } else {
+        String::new()
+    };
+    let position = fit(&position, cmp::min(position.len(), inner_width));
+    frame.push_str(&format!("{}{}\u{2514}{}{}\u{2518}{}", cursor::Goto(area.x, area.y + area.height - 1), border,
+                            "\u{2500}".repeat(inner_width.saturating_sub(position.chars().count())), position, style::Reset));
+    (scroll, rows)
+}
+
+/// Cuts or pads the line to exactly `width` visible characters, keeping color escape sequences intact
+fn fit(line: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut visible = 0;
+    let mut escaped = false;
+    let mut in_escape = false;
+    for c in line.chars() {
+        if in_escape {
+            fitted.push(c);
+            in_escape = !c.is_ascii_alphabetic();
+        } else if c == '\x1b' {
+            fitted.push(c);
+            in_escape = true;
+            escaped = true;
+        } else if visible < width {
+            fitted.push(if c.is_control() { ' ' } else { c });
+            visible += 1;
+        }
+    }
+    if escaped {
+        fitted.push_str(style::Reset.as_ref());
+    }
+    fitted.push_str(&" ".repeat(width - visible));
+    fitted
+}
@@ -0,0 +1,95 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Instant;
+
+use net2::{UdpBuilder, UdpSocketExt};
+
+use super::actor::Command;
+use super::chord;
+use super::error::ChordError;
+use super::lifecycle::NodeState;
+use super::node::OtherNode;
+
+/// Announcement of a ring member, sent to the discovery multicast group
+#[derive(Serialize, Deserialize, Debug)]
+struct Announcement {
+    cluster: String,
+    node: OtherNode,
+}
+
+/// Interface to send and receive announcements on, loopback nodes stay on the loopback interface
+fn multicast_interface(node_ip_addr: SocketAddr) -> Ipv4Addr {
+    match node_ip_addr.ip() {
+        IpAddr::V4(ip) => ip,
+        IpAddr::V6(_) => Ipv4Addr::UNSPECIFIED,
+    }
+}
+
+/// Socket that is a member of the discovery multicast group.
+/// The port is shared, so several nodes on the same host can look for peers at the same time.
+fn bind_multicast_socket(interface: Ipv4Addr) -> Result<UdpSocket, ChordError> {
+    let builder = UdpBuilder::new_v4()?;
+    builder.reuse_address(true)?;
+    #[cfg(unix)]
+    {
+        use net2::unix::UnixUdpBuilderExt;
+        builder.reuse_port(true)?;
+    }
+    let socket = builder.bind((Ipv4Addr::UNSPECIFIED, chord::DISCOVERY_PORT))?;
+    socket.join_multicast_v4(&chord::DISCOVERY_MULTICAST_ADDR, &interface)?;
+    Ok(socket)
+}
+
+/// Listens for announcements of the cluster and returns the first announced node,
+/// or `None` if no node announced itself in time
+pub fn discover_seed(cluster: &str, node_ip_addr: SocketAddr) -> Result<Option<SocketAddr>, ChordError> {
+    info!("Looking for nodes of cluster '{}' on the local network...", cluster);
+    let socket = bind_multicast_socket(multicast_interface(node_ip_addr))?;
+    let deadline = Instant::now() + chord::DISCOVERY_TIMEOUT_INTERVAL;
+    let mut buf = [0; chord::MAX_ANNOUNCEMENT_SIZE];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let (len, sender) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match serde_json::from_slice::<Announcement>(&buf[..len]) {
+            Ok(announcement) => {
+                if announcement.cluster == cluster && *announcement.node.get_ip_addr() != node_ip_addr {
+                    info!("Discovered Node #{} at {}", announcement.node.get_id(), announcement.node.get_ip_addr());
+                    return Ok(Some(*announcement.node.get_ip_addr()));
+                }
+                debug!("Ignoring announcement of cluster '{}' from {}", announcement.cluster, sender);
+            }
+            Err(e) => debug!("Ignoring malformed announcement from {}: {}", sender, e),
+        }
+    }
+}
+
+/// Announces the node to the discovery multicast group while it is an active member of the ring
+pub fn announce(actor: Sender<Command>, cluster: String, node: OtherNode) -> Result<(), ChordError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_multicast_if_v4(&multicast_interface(*node.get_ip_addr()))?;
+    socket.set_multicast_loop_v4(true)?;
+    let announcement = serde_json::to_vec(&Announcement { cluster, node })?;
+    loop {
+        let (reply, state) = mpsc::channel();
+        if actor.send(Command::GetState { reply }).is_err() {
+            return Ok(());
+        }
+        if state.recv() == Ok(NodeState::Active) {
+            if let Err(e) = socket.send_to(&announcement, (chord::DISCOVERY_MULTICAST_ADDR, chord::DISCOVERY_PORT)) {
+                debug!("Unable to send announcement: {}", e);
+            }
+        }
+        thread::sleep(chord::DISCOVERY_ANNOUNCE_INTERVAL);
+    }
+}
@@ -0,0 +1,86 @@
+use std::{error, fmt, io};
+use std::net::{AddrParseError, SocketAddr};
+use std::num::ParseIntError;
+use std::str::Utf8Error;
+
+use num_bigint::BigInt;
+
+use super::lifecycle::NodeState;
+
+/// Errors of a chord node. The variants only carry descriptions, states and ids,
+/// so they can be sent back to other nodes in a `Response::Error`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum ChordError {
+    /// Reading from or writing to a socket or the terminal failed
+    Io(String),
+    /// A received message is no valid utf-8 encoded JSON message
+    InvalidMessage(String),
+    /// An address could not be parsed
+    InvalidAddress(String),
+    /// A command line argument or user input is invalid
+    InvalidArgument(String),
+    /// The node has not joined a chord ring yet and can't process requests
+    NotJoined,
+    /// The node is no active member of the ring and does not take over keys
+    Unavailable(NodeState),
+    /// The node lifecycle does not allow to change from the first to the second state
+    InvalidTransition(NodeState, NodeState),
+    /// The id of a joining node is already taken by the node at the given address
+    IdCollision(BigInt, SocketAddr),
+    /// None of the given seed nodes let the node join before the deadline
+    JoinTimeout(String),
+    /// No reachable node is left to answer a request
+    Unreachable(String),
+    /// The result of a request did not arrive before the deadline
+    Timeout,
+}
+
+impl fmt::Display for ChordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChordError::Io(msg) => write!(f, "I/O error: {}", msg),
+            ChordError::InvalidMessage(msg) => write!(f, "Invalid message: {}", msg),
+            ChordError::InvalidAddress(msg) => write!(f, "Invalid address: {}", msg),
+            ChordError::InvalidArgument(msg) => write!(f, "Invalid argument: {}", msg),
+            ChordError::NotJoined => write!(f, "Node has not joined a chord ring yet"),
+            ChordError::Unavailable(state) => write!(f, "Node is {} and does not own keys", state),
+            ChordError::InvalidTransition(from, to) => write!(f, "Node can't change from {} to {}", from, to),
+            ChordError::IdCollision(id, addr) => write!(f, "Node ID #{} is already taken by the node at {}", id, addr),
+            ChordError::JoinTimeout(seeds) => write!(f, "Unable to join the chord ring via any of the seed nodes {} before the deadline", seeds),
+            ChordError::Unreachable(msg) => write!(f, "{}", msg),
+            ChordError::Timeout => write!(f, "No result arrived before the deadline"),
+        }
+    }
+}
+
+impl error::Error for ChordError {}
+
+impl From<io::Error> for ChordError {
+    fn from(e: io::Error) -> ChordError {
+        ChordError::Io(e.to_string())
+    }
+}
+
+impl From<Utf8Error> for ChordError {
+    fn from(e: Utf8Error) -> ChordError {
+        ChordError::InvalidMessage(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for ChordError {
+    fn from(e: serde_json::Error) -> ChordError {
+        ChordError::InvalidMessage(e.to_string())
+    }
+}
+
+impl From<AddrParseError> for ChordError {
+    fn from(e: AddrParseError) -> ChordError {
+        ChordError::InvalidAddress(e.to_string())
+    }
+}
+
+impl From<ParseIntError> for ChordError {
+    fn from(e: ParseIntError) -> ChordError {
+        ChordError::InvalidArgument(e.to_string())
+    }
+}
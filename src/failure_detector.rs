@@ -0,0 +1,170 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use num_bigint::BigInt;
+
+use super::chord;
+
+/// Number of heartbeat intervals and round trip times that are kept per peer
+const HISTORY_SIZE: usize = 100;
+
+/// Lower bound for the standard deviation, so a very regular peer is not suspected on the first delay
+const MIN_STD_DEVIATION_MS: f64 = 100.0;
+
+/// Heartbeat history of a single peer
+#[derive(Clone)]
+struct PeerHistory {
+    last_heartbeat: Instant,
+    intervals: VecDeque<f64>,
+    rtts: VecDeque<Duration>,
+}
+
+impl PeerHistory {
+    fn new(now: Instant) -> PeerHistory {
+        // Start with an estimate of the expected interval, so a peer that never answers gets suspected
+        let mut intervals = VecDeque::with_capacity(HISTORY_SIZE);
+        intervals.push_back(duration_to_ms(chord::NODE_PING_INTERVAL));
+        PeerHistory {
+            last_heartbeat: now,
+            intervals,
+            rtts: VecDeque::with_capacity(HISTORY_SIZE),
+        }
+    }
+
+    fn heartbeat(&mut self, rtt: Duration, now: Instant) {
+        push_bounded(&mut self.intervals, duration_to_ms(now - self.last_heartbeat));
+        push_bounded(&mut self.rtts, rtt);
+        self.last_heartbeat = now;
+    }
+
+    /// Suspicion level that the peer has crashed, based on how unlikely it is to
+    /// not have received a heartbeat for the current time given the past intervals
+    fn phi(&self, now: Instant) -> f64 {
+        let count = self.intervals.len() as f64;
+        let mean = self.intervals.iter().sum::<f64>() / count;
+        let variance = self.intervals.iter().map(|i| (i - mean) * (i - mean)).sum::<f64>() / count;
+        let std_deviation = variance.sqrt().max(MIN_STD_DEVIATION_MS);
+
+        let time_diff = duration_to_ms(now.saturating_duration_since(self.last_heartbeat));
+        // Logistic approximation of the cumulative normal distribution
+        let y = (time_diff - mean) / std_deviation;
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if time_diff > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+
+    fn mean_rtt(&self) -> Option<Duration> {
+        if self.rtts.is_empty() {
+            None
+        } else {
+            Some(self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32)
+        }
+    }
+}
+
+/// Phi accrual failure detector keeping a suspicion score for every watched peer
+#[derive(Clone)]
+pub struct FailureDetector {
+    peers: HashMap<BigInt, PeerHistory>,
+}
+
+impl FailureDetector {
+    pub fn new() -> FailureDetector {
+        FailureDetector { peers: HashMap::new() }
+    }
+
+    /// Starts monitoring the given peers and stops monitoring all others
+    pub fn watch(&mut self, peer_ids: &[BigInt], now: Instant) {
+        self.peers.retain(|id, _| peer_ids.contains(id));
+        for id in peer_ids {
+            self.peers.entry(id.clone()).or_insert_with(|| PeerHistory::new(now));
+        }
+    }
+
+    pub fn heartbeat(&mut self, peer_id: &BigInt, rtt: Duration, now: Instant) {
+        if let Some(history) = self.peers.get_mut(peer_id) {
+            history.heartbeat(rtt, now);
+        }
+    }
+
+    /// Peers that are not watched yet are not suspected
+    pub fn phi(&self, peer_id: &BigInt, now: Instant) -> f64 {
+        self.peers.get(peer_id).map_or(0.0, |history| history.phi(now))
+    }
+
+    pub fn is_available(&self, peer_id: &BigInt, now: Instant) -> bool {
+        self.phi(peer_id, now) < chord::PHI_THRESHOLD
+    }
+
+    pub fn get_rtt(&self, peer_id: &BigInt) -> Option<Duration> {
+        self.peers.get(peer_id).and_then(|history| history.mean_rtt())
+    }
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T) {
+    if queue.len() == HISTORY_SIZE {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+fn duration_to_ms(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + f64::from(duration.subsec_nanos()) / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use num_bigint::BigInt;
+
+    use super::FailureDetector;
+    use super::super::chord;
+
+    /// Detector watching a single peer that answered `count` pings in regular intervals
+    fn with_heartbeats(peer_id: &BigInt, count: u32, start: Instant) -> (FailureDetector, Instant) {
+        let mut detector = FailureDetector::new();
+        detector.watch(std::slice::from_ref(peer_id), start);
+        let mut now = start;
+        for i in 0..count {
+            now += chord::NODE_PING_INTERVAL;
+            detector.heartbeat(peer_id, Duration::from_millis(10 + u64::from(i % 2) * 10), now);
+        }
+        (detector, now)
+    }
+
+    #[test]
+    fn phi_stays_low_on_regular_heartbeats() {
+        let peer_id = BigInt::from(1);
+        let (detector, last) = with_heartbeats(&peer_id, 20, Instant::now());
+        assert!(detector.phi(&peer_id, last) < 1.0);
+        assert!(detector.phi(&peer_id, last + chord::NODE_PING_INTERVAL) < chord::PHI_THRESHOLD);
+        assert!(detector.is_available(&peer_id, last + chord::NODE_PING_INTERVAL));
+        assert_eq!(detector.get_rtt(&peer_id), Some(Duration::from_millis(15)));
+    }
+
+    #[test]
+    fn phi_exceeds_the_threshold_after_a_gap() {
+        let peer_id = BigInt::from(1);
+        let (detector, last) = with_heartbeats(&peer_id, 20, Instant::now());
+        let short = detector.phi(&peer_id, last + chord::NODE_PING_INTERVAL * 2);
+        let long = detector.phi(&peer_id, last + chord::NODE_PING_INTERVAL * 4);
+        assert!(short < long, "phi does not grow with the gap: {} vs. {}", short, long);
+        assert!(long > chord::PHI_THRESHOLD);
+        assert!(!detector.is_available(&peer_id, last + chord::NODE_PING_INTERVAL * 4));
+    }
+
+    #[test]
+    fn peers_without_heartbeats_are_judged_by_the_ping_interval() {
+        let start = Instant::now();
+        let (peer_id, unknown) = (BigInt::from(1), BigInt::from(2));
+        let (detector, _) = with_heartbeats(&peer_id, 0, start);
+        assert_eq!(detector.phi(&unknown, start + Duration::from_secs(60)), 0.0);
+        assert!(detector.is_available(&peer_id, start + chord::NODE_PING_INTERVAL));
+        assert!(!detector.is_available(&peer_id, start + chord::NODE_PING_INTERVAL * 4));
+        assert_eq!(detector.get_rtt(&peer_id), None);
+    }
+}
@@ -1,174 +1,450 @@
-use std::{error::Error};
-use std::io::stdin;
+use std::collections::BTreeSet;
 use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use linefeed::{Completer, Completion, DefaultTerminal, Interface, Prompter, ReadResult, Signal, Terminal};
+
+use super::actor::Command;
+use super::admin::NodeStatus;
 use super::chord;
+use super::error::ChordError;
 use super::network;
 use super::node::OtherNode;
+use super::operation::OperationResult;
 use super::protocols::*;
+use super::snapshot;
 use super::storage;
+use super::storage::WriteCondition;
 
-pub fn perform_user_interaction(node_as_other: OtherNode) -> Result<(), Box<Error>> {
-    let info =
-        "\n \nHello there! What do you want to do?\n \n\
-        1 - Store a key/value pair in the Chord network\n\
-        2 - Find the value for a given key in the Chord network\n\
-        3 - Delete a key/value pair from the Chord network\n\n\
-        4 - Kill a Chord network peer\n\n\
-        5 - Cancel interaction\n\
-        6 - Terminate Node\n\n\
-        Choose 1, 2, 3, 4, 5 or 6 and press Enter!";
-    print!("{}[2J", 27 as char);
-    info!("{}", info);
+const COMMANDS: [&str; 23] = [
+    "put", "putnx", "cas", "mput", "get", "mget", "del", "owner", "watch", "unwatch",
+    "sub", "unsub", "pub", "export", "export-ring", "import", "ring", "fingers", "succ", "status",
+    "kill", "help", "quit",
+];
 
-    loop {
-        let buffer = &mut String::new();
-        stdin().read_line(buffer).unwrap();
-        match buffer.trim_right() {
-            "1" => {
-                store(node_as_other.clone()).expect("store failed");
-                break;
-            }
-            "2" => {
-                find(node_as_other.clone()).expect("find failed");
-                break;
-            }
-            "3" => {
-                delete(node_as_other.clone()).expect("delete failed");
-                break;
-            }
-            "4" => {
-                kill().expect("kill failed");
-                break;
-            }
-            "5" => {
-                break;
-            }
-            "6" => {
-                kill_node(*node_as_other.get_ip_addr());
+const HELP: &str = "\
+put <key> <value> [ttl]            Store a key/value pair, expiring after ttl seconds if given\n\
+putnx <key> <value> [ttl]          Store a key/value pair only if the key is absent\n\
+cas <key> <version> <value> [ttl]  Store a key/value pair only if the key has the version (0: absent)\n\
+mput <key>=<value>...              Store multiple key/value pairs\n\
+get <key>                          Find the value of a key\n\
+mget <key>...                      Find the values of multiple keys\n\
+del <key>                          Delete a key/value pair\n\
+owner <key>                        Find the node responsible for a key\n\
+watch <key>                        Report every change of a key\n\
+unwatch <key>                      Stop reporting changes of a key\n\
+sub <topic>                        Subscribe to a topic\n\
+unsub <topic>                      Unsubscribe from a topic\n\
+pub <topic> <message>              Publish a message to all subscribers of a topic\n\
+export <file>                      Write the keys of this node to a JSON lines file\n\
+export-ring <file>                 Write the keys of all nodes of the ring to a JSON lines file\n\
+import <file>                      Store all keys of an exported file in the ring\n\
+ring                               Show the ring as far as this node knows it\n\
+fingers                            Show the finger table\n\
+succ                               Show the successor list\n\
+status                             Print the node state tables\n\
+kill <ip:port>                     Kill a chord network peer\n\
+help                               Show this help\n\
+quit                               Terminate this node\n\
+Use double quotes for keys and values with spaces, e.g. put name \"Alice Smith\"";
+
+/// Read-eval-print loop for the DHT and the node. Results of DHT requests arrive
+/// asynchronously and are printed above the prompt as soon as they show up in the storage log.
+pub fn run_repl(actor: Sender<Command>, node_as_other: OtherNode) -> Result<(), ChordError> {
+    let interface = Arc::new(Interface::new("hll-chord")?);
+    interface.set_prompt(&format!("chord #{}> ", node_as_other.get_id()))?;
+    let completer = Arc::new(ReplCompleter::new());
+    interface.set_completer(completer.clone());
+    interface.set_report_signal(Signal::Interrupt, true);
+
+    let interface_clone = interface.clone();
+    let actor_clone = actor.clone();
+    thread::Builder::new().name("Repl_Log".to_string()).spawn(move || {
+        let mut printed = 0;
+        loop {
+            let (reply, entries) = mpsc::channel();
+            if actor_clone.send(Command::GetLog { since: printed, reply }).is_err() {
                 break;
             }
-            _ => {
-                println!("Please choose an valid option [1,2,3,4,5]");
+            let entries = match entries.recv() {
+                Ok((entries, next)) => {
+                    printed = next;
+                    entries
+                }
+                Err(_) => break,
+            };
+            for entry in entries {
+                if let Err(e) = writeln!(interface_clone, "{}", entry) {
+                    error!("Unable to write to terminal: {}", e);
+                    return;
+                }
             }
-        };
-    }
-
-    Ok(())
-}
+            thread::sleep(chord::REPL_LOG_POLL_INTERVAL);
+        }
+    })?;
 
-fn store(node_as_other: OtherNode) -> Result<(), Box<Error>> {
+    writeln!(interface, "Type 'help' for a list of commands, TAB completes commands and keys")?;
     loop {
-        println!("Enter the string that should be used as a KEY\n\
-        (p.e.: A name):");
-        let buffer = &mut String::new();
-        stdin().read_line(buffer)?;
-        match buffer.trim_right() {
-            "" => {
-                println!("Please Enter a valid Key name.");
-            }
-            k => {
-                let key = k.to_string();
-                let value;
-                loop {
-                    println!("Enter the string that should be stored as value for key {} \n\
-                    (p.e.: A phone number)", key.clone());
-                    let buffer2 = &mut String::new();
-                    stdin().read_line(buffer2)?;
-                    match buffer2.trim_right() {
-                        "" => {
-                            println!("Please Enter a valid value.");
-                        }
-                        v => {
-                            value = v.to_owned();
-                            break;
+        match interface.read_line()? {
+            ReadResult::Input(line) => {
+                let words = match split_words(&line) {
+                    Ok(words) => words,
+                    Err(e) => {
+                        writeln!(interface, "{}", e)?;
+                        continue;
+                    }
+                };
+                if words.is_empty() {
+                    continue;
+                }
+                interface.add_history_unique(line.clone());
+                if words[0] == "quit" {
+                    kill_node(*node_as_other.get_ip_addr());
+                    break;
+                }
+                match execute_command(&words, &actor, &node_as_other, &completer, &interface) {
+                    Ok(output) => {
+                        if !output.is_empty() {
+                            writeln!(interface, "{}", output)?;
                         }
                     }
+                    Err(e) => writeln!(interface, "{}", e)?,
                 }
-                store_key_value(key, value, node_as_other);
+            }
+            ReadResult::Signal(Signal::Interrupt) => {
+                // Same as SIGINT without a terminal: hand over the keys and terminate
+                let _ = actor.send(Command::Shutdown);
                 break;
             }
+            ReadResult::Signal(_) => {}
+            ReadResult::Eof => break,
         }
-    };
+    }
     Ok(())
 }
 
-fn find(node_as_other: OtherNode) -> Result<(), Box<Error>> {
-    loop {
-        println!("Enter a Key to look for in the network:");
-        let buffer = &mut String::new();
-        stdin().read_line(buffer)?;
-        match buffer.trim_right() {
-            "" => {
-                println!("Please Enter a valid Key name.");
+fn execute_command(words: &[String],
+                   actor: &Sender<Command>,
+                   node_as_other: &OtherNode,
+                   completer: &ReplCompleter,
+                   interface: &Interface<DefaultTerminal>) -> Result<String, ChordError> {
+    // Progress of exports and imports is printed while the command runs
+    let progress = |line: String| {
+        if let Err(e) = writeln!(interface, "{}", line) {
+            error!("Unable to write to terminal: {}", e);
+        }
+    };
+    let args = &words[1..];
+    match (words[0].as_str(), args.len()) {
+        ("put", 2) | ("put", 3) => {
+            let ttl = parse_ttl(args.get(2))?;
+            completer.remember(&args[0]);
+            store_key_value(args[0].clone(), args[1].clone(), ttl, node_as_other.clone());
+            Ok(format!("Storing key '{}'...", args[0]))
+        }
+        ("putnx", 2) | ("putnx", 3) => {
+            let ttl = parse_ttl(args.get(2))?;
+            completer.remember(&args[0]);
+            store_key_value_if(args[0].clone(), args[1].clone(), ttl, WriteCondition::IfAbsent, node_as_other.clone());
+            Ok(format!("Storing key '{}' if it is absent...", args[0]))
+        }
+        ("cas", 3) | ("cas", 4) => {
+            let version = parse_version(&args[1])?;
+            let ttl = parse_ttl(args.get(3))?;
+            completer.remember(&args[0]);
+            store_key_value_if(args[0].clone(), args[2].clone(), ttl, WriteCondition::IfVersion(version), node_as_other.clone());
+            Ok(format!("Storing key '{}' if it has version {}...", args[0], version))
+        }
+        ("mput", n) if n > 0 => {
+            let pairs = parse_pairs(args)?;
+            for (key, _) in &pairs {
+                completer.remember(key);
             }
-            k => {
-                let key = k.to_string();
-                find_key(key, node_as_other);
-                break;
+            store_key_values(actor, pairs)?;
+            Ok(format!("Storing {} keys...", args.len()))
+        }
+        ("get", 1) => {
+            completer.remember(&args[0]);
+            find_key(args[0].clone(), node_as_other.clone());
+            Ok(format!("Looking for key '{}' (id: {})...", args[0], chord::create_id(&args[0])))
+        }
+        ("mget", n) if n > 0 => {
+            for key in args {
+                completer.remember(key);
             }
+            find_keys(actor, args.to_vec())?;
+            Ok(format!("Looking for {} keys...", args.len()))
         }
-    };
-    Ok(())
-}
-
-fn delete(node_as_other: OtherNode) -> Result<(), Box<Error>> {
-    loop {
-        println!("Enter a Key to look for in the network:");
-        let buffer = &mut String::new();
-        stdin().read_line(buffer)?;
-        match buffer.trim_right() {
-            "" => {
-                println!("Please Enter a valid Key name.");
+        ("del", 1) => {
+            completer.remember(&args[0]);
+            delete_key(args[0].clone(), node_as_other.clone());
+            Ok(format!("Deleting key '{}' (id: {})...", args[0], chord::create_id(&args[0])))
+        }
+        ("owner", 1) => {
+            completer.remember(&args[0]);
+            find_owner(args[0].clone(), node_as_other.clone());
+            Ok(format!("Looking for the owner of key '{}' (id: {})...", args[0], chord::create_id(&args[0])))
+        }
+        ("watch", 1) => {
+            completer.remember(&args[0]);
+            watch_key(actor, args[0].clone())?;
+            Ok(format!("Watching key '{}' (id: {})...", args[0], chord::create_id(&args[0])))
+        }
+        ("unwatch", 1) => {
+            unwatch_key(actor, args[0].clone())?;
+            Ok(String::new())
+        }
+        ("sub", 1) => {
+            completer.remember_topic(&args[0]);
+            subscribe(actor, args[0].clone())?;
+            Ok(format!("Subscribing to topic '{}' (id: {})...", args[0], chord::create_id(&args[0])))
+        }
+        ("unsub", 1) => {
+            unsubscribe(actor, args[0].clone())?;
+            Ok(String::new())
+        }
+        ("pub", 2) => {
+            completer.remember_topic(&args[0]);
+            publish(args[0].clone(), args[1].clone(), node_as_other.clone());
+            Ok(format!("Publishing to topic '{}'...", args[0]))
+        }
+        ("export", 1) => Ok(snapshot::export(actor, &args[0], false, progress)?.to_string()),
+        ("export-ring", 1) => Ok(snapshot::export(actor, &args[0], true, progress)?.to_string()),
+        ("import", 1) => Ok(snapshot::import(actor, &args[0], progress)?.to_string()),
+        ("ring", 0) => {
+            let status = get_status(actor)?;
+            // Follow the successor list until it wraps around to this node
+            let mut lines = vec![format!("{:>9}  {}  (this node)", status.get_id(), status.get_ip_addr())];
+            for succ in status.get_successor_list().iter().take_while(|succ| succ.get_id() != status.get_id()) {
+                lines.push(format!("{:>9}  {}", succ.get_id(), succ.get_ip_addr()));
             }
-            k => {
-                let key = k.to_string();
-                delete_key(key, node_as_other);
-                break;
+            if let Some(pre) = status.get_predecessor() {
+                lines.push(format!("Predecessor: #{} ({})", pre.get_id(), pre.get_ip_addr()));
             }
+            Ok(lines.join("\n"))
         }
-    };
-    Ok(())
+        ("fingers", 0) => {
+            let status = get_status(actor)?;
+            let mut lines = vec![format!("{:>2}  {:>9}  {:>9}  {}", "#", "Finger", "Node", "SocketAddr")];
+            for (i, (finger_id, node)) in status.get_fingers().iter().enumerate() {
+                lines.push(format!("{:>2}  {:>9}  {:>9}  {}", i, finger_id, node.get_id(), node.get_ip_addr()));
+            }
+            Ok(lines.join("\n"))
+        }
+        ("succ", 0) => {
+            let status = get_status(actor)?;
+            let mut lines = vec![format!("{:>2}  {:>9}  {}", "#", "ID", "SocketAddr")];
+            for (i, succ) in status.get_successor_list().iter().enumerate() {
+                lines.push(format!("{:>2}  {:>9}  {}", i, succ.get_id(), succ.get_ip_addr()));
+            }
+            Ok(lines.join("\n"))
+        }
+        ("status", 0) => {
+            actor.send(Command::Print).map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+            Ok(String::new())
+        }
+        ("kill", 1) => {
+            let target_ip = args[0].parse::<SocketAddr>()?;
+            kill_node(target_ip);
+            Ok(format!("Killing node {}...", target_ip))
+        }
+        ("help", _) => Ok(HELP.to_string()),
+        (command, _) if COMMANDS.contains(&command) => {
+            Err(ChordError::InvalidArgument(format!("wrong number of arguments for '{}', type 'help' for the usage", command)))
+        }
+        (command, _) => Err(ChordError::InvalidArgument(format!("unknown command '{}', type 'help' for a list of commands", command))),
+    }
 }
 
-fn kill() -> Result<(), Box<Error>> {
-    loop {
-        println!("Enter <IP>:<Port> (i.e. 127.0.0.1:10000) of a to be killed chord network peer:");
-        let buffer = &mut String::new();
-        stdin().read_line(buffer)?;
-        match buffer.trim_right() {
-            "" => {
-                println!("Please enter a valid SocketAddr.");
+pub fn get_status(actor: &Sender<Command>) -> Result<NodeStatus, ChordError> {
+    let (reply, status) = mpsc::channel();
+    actor.send(Command::GetStatus { reply }).map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+    status.recv().map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+pub fn parse_ttl(arg: Option<&String>) -> Result<Option<Duration>, ChordError> {
+    match arg {
+        Some(secs) => secs.parse::<u64>()
+            .map(|secs| Some(Duration::from_secs(secs)))
+            .map_err(|e| ChordError::InvalidArgument(format!("ttl '{}': {}", secs, e))),
+        None => Ok(None),
+    }
+}
+
+pub fn parse_version(arg: &str) -> Result<u64, ChordError> {
+    arg.parse::<u64>().map_err(|e| ChordError::InvalidArgument(format!("version '{}': {}", arg, e)))
+}
+
+/// Splits the `KEY=VALUE` arguments of `mput`
+pub fn parse_pairs(args: &[String]) -> Result<Vec<(String, String)>, ChordError> {
+    args.iter()
+        .map(|arg| match arg.find('=') {
+            Some(index) if index > 0 && index < arg.len() - 1 => Ok((arg[..index].to_string(), arg[index + 1..].to_string())),
+            _ => Err(ChordError::InvalidArgument(format!("'{}' is not a KEY=VALUE pair", arg))),
+        })
+        .collect()
+}
+
+/// Splits the line at whitespace, except inside double quotes
+pub fn split_words(line: &str) -> Result<Vec<String>, ChordError> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
             }
-            k => {
-                let ip_string = k.to_string();
-                let target_ip = ip_string.parse::<SocketAddr>().unwrap();
-                kill_node(target_ip);
-                break;
+            c if c.is_whitespace() && !quoted => {
+                if let Some(word) = word.take() {
+                    words.push(word);
+                }
             }
+            c => word.get_or_insert_with(String::new).push(c),
         }
-    };
-    Ok(())
+    }
+    if quoted {
+        return Err(ChordError::InvalidArgument("missing closing quote".to_string()));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Completes command names and the keys and topics used in this session
+struct ReplCompleter {
+    keys: Mutex<BTreeSet<String>>,
+    topics: Mutex<BTreeSet<String>>,
+}
+
+impl ReplCompleter {
+    fn new() -> ReplCompleter {
+        ReplCompleter { keys: Mutex::new(BTreeSet::new()), topics: Mutex::new(BTreeSet::new()) }
+    }
+
+    fn remember(&self, key: &str) {
+        if let Ok(mut keys) = self.keys.lock() {
+            keys.insert(key.to_string());
+        }
+    }
+
+    fn remember_topic(&self, topic: &str) {
+        if let Ok(mut topics) = self.topics.lock() {
+            topics.insert(topic.to_string());
+        }
+    }
+}
+
+impl<Term: Terminal> Completer<Term> for ReplCompleter {
+    fn complete(&self, word: &str, prompter: &Prompter<Term>, start: usize, _end: usize) -> Option<Vec<Completion>> {
+        let previous: Vec<&str> = prompter.buffer()[..start].split_whitespace().collect();
+        let candidates: Vec<String> = match previous.as_slice() {
+            [] => COMMANDS.iter().map(|command| command.to_string()).collect(),
+            ["put"] | ["putnx"] | ["cas"] | ["get"] | ["del"] | ["owner"] | ["watch"] | ["unwatch"] | ["mget", ..] => match self.keys.lock() {
+                Ok(keys) => keys.iter().cloned().collect(),
+                Err(_) => Vec::new(),
+            },
+            ["sub"] | ["unsub"] | ["pub"] => match self.topics.lock() {
+                Ok(topics) => topics.iter().cloned().collect(),
+                Err(_) => Vec::new(),
+            },
+            _ => Vec::new(),
+        };
+        Some(candidates.into_iter()
+            .filter(|candidate| candidate.starts_with(word))
+            .map(Completion::simple)
+            .collect())
+    }
 }
 
-fn kill_node(target_ip: SocketAddr) {
+pub fn kill_node(target_ip: SocketAddr) {
     network::send_kill(target_ip);
 }
 
-fn store_key_value(key: String, value: String, node_as_other: OtherNode) {
-    let req = Request::DHTStoreKey { data: storage::make_hashed_key_value_pair(key, value) };
+pub fn store_key_value(key: String, value: String, ttl: Option<Duration>, node_as_other: OtherNode) {
+    let req = Request::DHTStoreKey { data: storage::make_hashed_key_value_pair(key, value, ttl) };
+    info!("Trying to store data {:?}", req.clone());
+    network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+}
+
+fn store_key_value_if(key: String, value: String, ttl: Option<Duration>, condition: WriteCondition, node_as_other: OtherNode) {
+    let req = Request::DHTStoreKeyIf { data: storage::make_hashed_key_value_pair(key, value, ttl), condition };
     info!("Trying to store data {:?}", req.clone());
     network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
 }
 
-fn find_key(key: String, node_as_other: OtherNode) {
+pub fn find_key(key: String, node_as_other: OtherNode) {
     let key_id = chord::create_id(&key);
     let req = Request::DHTFindKey { key_id };
     network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
 }
 
-fn delete_key(key: String, node_as_other: OtherNode) {
+fn store_key_values(actor: &Sender<Command>, pairs: Vec<(String, String)>) -> Result<(), ChordError> {
+    let data = pairs.into_iter()
+        .map(|(key, value)| storage::make_hashed_key_value_pair(key, value, None))
+        .collect();
+    let request = Request::DHTStoreKeys { batch_id: create_batch_id(), data };
+    info!("Trying to store data {:?}", request.clone());
+    actor.send(Command::Request { request: Box::new(request), reply: None }).map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+fn find_keys(actor: &Sender<Command>, keys: Vec<String>) -> Result<(), ChordError> {
+    let key_ids = keys.iter().map(|key| chord::create_id(key)).collect();
+    let request = Request::DHTFindKeys { batch_id: create_batch_id(), key_ids };
+    actor.send(Command::Request { request: Box::new(request), reply: None }).map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+/// Sends a DHT request via the node actor and waits until its result arrived
+pub fn execute_request(actor: &Sender<Command>, request: Request, timeout: Duration) -> Result<OperationResult, ChordError> {
+    let (reply, result) = mpsc::channel();
+    actor.send(Command::Request { request: Box::new(request), reply: Some(reply) })
+        .map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+    match result.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(ChordError::Timeout),
+        Err(RecvTimeoutError::Disconnected) => Err(ChordError::Io("node actor stopped".to_string())),
+    }
+}
+
+pub fn create_batch_id() -> u64 {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    since_epoch.as_secs() * 1_000_000_000 + u64::from(since_epoch.subsec_nanos())
+}
+
+pub fn delete_key(key: String, node_as_other: OtherNode) {
     let key_id = chord::create_id(&key);
     let req = Request::DHTDeleteKey { key_id };
     network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
 }
+
+pub fn find_owner(key: String, node_as_other: OtherNode) {
+    let key_id = chord::create_id(&key);
+    let req = Request::DHTFindOwner { key_id };
+    network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+}
+
+pub fn watch_key(actor: &Sender<Command>, key: String) -> Result<(), ChordError> {
+    actor.send(Command::Watch { key }).map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+pub fn unwatch_key(actor: &Sender<Command>, key: String) -> Result<(), ChordError> {
+    actor.send(Command::Unwatch { key }).map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+pub fn subscribe(actor: &Sender<Command>, topic: String) -> Result<(), ChordError> {
+    actor.send(Command::Subscribe { topic }).map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+pub fn unsubscribe(actor: &Sender<Command>, topic: String) -> Result<(), ChordError> {
+    actor.send(Command::Unsubscribe { topic }).map_err(|_| ChordError::Io("node actor stopped".to_string()))
+}
+
+pub fn publish(topic: String, message: String, node_as_other: OtherNode) {
+    let topic_id = chord::create_id(&topic);
+    let req = Request::Publish { topic_id, topic, message };
+    network::send_request(node_as_other.clone(), node_as_other.get_ip_addr().to_owned(), req);
+}
@@ -0,0 +1,84 @@
+use std::fmt;
+
+/// Lifecycle of a node, from joining the ring to leaving it
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NodeState {
+    /// Looking for its successor via the entry point
+    Joining,
+    /// Member of the ring, responsible for the keys between its predecessor and itself
+    Active,
+    /// Waiting for the successor to take over its keys, still routes requests
+    Draining,
+    /// Keys are handed over, the node is about to terminate
+    Leaving,
+    /// Lost contact to the ring
+    Failed,
+}
+
+impl NodeState {
+    pub fn can_transition_to(self, next: NodeState) -> bool {
+        use self::NodeState::*;
+        matches!((self, next),
+            (Joining, Active) | (Joining, Leaving) | (Joining, Failed)
+            | (Active, Draining) | (Active, Leaving) | (Active, Failed)
+            | (Draining, Leaving) | (Draining, Failed)
+            | (Failed, Joining) | (Failed, Leaving))
+    }
+
+    /// Whether the node is part of the ring and takes part in routing and stabilization
+    pub fn is_member(self) -> bool {
+        self == NodeState::Active || self == NodeState::Draining
+    }
+
+    /// Whether the node may store keys and answer key lookups
+    pub fn owns_keys(self) -> bool {
+        self == NodeState::Active
+    }
+}
+
+impl fmt::Display for NodeState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            NodeState::Joining => "joining",
+            NodeState::Active => "active",
+            NodeState::Draining => "draining",
+            NodeState::Leaving => "leaving",
+            NodeState::Failed => "failed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeState;
+    use super::NodeState::*;
+
+    const STATES: [NodeState; 5] = [Joining, Active, Draining, Leaving, Failed];
+
+    #[test]
+    fn only_the_lifecycle_transitions_are_allowed() {
+        let allowed = [
+            (Joining, Active), (Joining, Leaving), (Joining, Failed),
+            (Active, Draining), (Active, Leaving), (Active, Failed),
+            (Draining, Leaving), (Draining, Failed),
+            (Failed, Joining), (Failed, Leaving),
+        ];
+        for from in STATES.iter() {
+            for to in STATES.iter() {
+                let expected = allowed.contains(&(*from, *to));
+                assert_eq!(from.can_transition_to(*to), expected, "{} -> {}", from, to);
+            }
+        }
+        assert!(!Leaving.can_transition_to(Active));
+        assert!(!Draining.can_transition_to(Active));
+    }
+
+    #[test]
+    fn only_active_nodes_own_keys() {
+        let members: Vec<NodeState> = STATES.iter().cloned().filter(|state| state.is_member()).collect();
+        let owners: Vec<NodeState> = STATES.iter().cloned().filter(|state| state.owns_keys()).collect();
+        assert_eq!(members, vec![Active, Draining]);
+        assert_eq!(owners, vec![Active]);
+    }
+}
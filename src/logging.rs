@@ -0,0 +1,208 @@
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use chrono::Local;
+use log::{LevelFilter, Record};
+use log4rs::append::console::{ConsoleAppender, Target};
+use log4rs::append::rolling_file::policy::compound::CompoundPolicy;
+use log4rs::append::rolling_file::policy::compound::roll::delete::DeleteRoller;
+use log4rs::append::rolling_file::policy::compound::trigger::size::SizeTrigger;
+use log4rs::append::rolling_file::RollingFileAppender;
+use log4rs::config::{Appender, Config, Root};
+use log4rs::encode::{Encode, Write};
+use log4rs::encode::pattern::PatternEncoder;
+use log4rs::file::{Deserializers, RawConfig};
+use log4rs::filter::threshold::ThresholdFilter;
+use log4rs::Handle;
+use log_mdc::ExtendGuard;
+use num_bigint::BigInt;
+
+use super::chord;
+use super::chord::Frontend;
+use super::error::ChordError;
+use super::protocols::Message;
+
+const CONSOLE_PATTERN: &str = "{d(%+)(local)} {h({l})} [{T}] {h({f}:{L})} - {m}{n}";
+const PLAIN_PATTERN: &str = "{d(%+)(local)} {l} [{T}] {f}:{L} - {m}{n}";
+
+/// log4rs YAML file that is used instead of the built-in configuration if it exists
+const CONFIG_FILE: &str = "config/log4rs.yaml";
+
+/// Logging settings of the command line
+pub struct LogOptions {
+    /// Overrides the level of the root logger
+    pub level: Option<LevelFilter>,
+    /// Writes JSON lines instead of plain text to the log files, the daemon console always does
+    pub json: bool,
+    /// log4rs YAML file to use instead of `config/log4rs.yaml` or the built-in configuration
+    pub config: Option<String>,
+}
+
+/// Initializes the logger with a console on stderr, until the node knows its id and port
+pub fn init(level: Option<LevelFilter>) -> Result<Handle, ChordError> {
+    let console = ConsoleAppender::builder()
+        .target(Target::Stderr)
+        .encoder(Box::new(PatternEncoder::new(PLAIN_PATTERN)))
+        .build();
+    let config = Config::builder()
+        .appender(Appender::builder().build("console", Box::new(console)))
+        .build(Root::builder().appender("console").build(level.unwrap_or(LevelFilter::Info)))
+        .map_err(|e| ChordError::InvalidArgument(format!("log config: {}", e)))?;
+    log4rs::init_config(config).map_err(|e| ChordError::InvalidArgument(format!("log config: {}", e)))
+}
+
+/// Switches the logger to the configuration of the node: the file given with `--log-config`, else
+/// `config/log4rs.yaml` if it exists, else the built-in configuration, which `--log-json` always selects.
+/// The log files are named by node id and port, so several nodes on one host don't write to the same files
+pub fn configure(handle: &Handle, options: &LogOptions, frontend: &Frontend, id: &BigInt, port: u16) -> Result<(), ChordError> {
+    let config = match &options.config {
+        Some(path) => load_config(path, options.level, id, port)?,
+        None if !options.json && Path::new(CONFIG_FILE).is_file() => load_config(CONFIG_FILE, options.level, id, port)?,
+        None => default_config(options, frontend, id, port)?,
+    };
+    handle.set_config(config);
+    Ok(())
+}
+
+/// Loads a log4rs YAML file, `${id}` and `${port}` are replaced by the id and port of the node
+fn load_config(path: &str, level: Option<LevelFilter>, id: &BigInt, port: u16) -> Result<Config, ChordError> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| ChordError::InvalidArgument(format!("log config FILE '{}': {}", path, e)))?
+        .replace("${id}", &id.to_string())
+        .replace("${port}", &port.to_string());
+    let raw: RawConfig = serde_yaml::from_str(&source)
+        .map_err(|e| ChordError::InvalidArgument(format!("log config FILE '{}': {}", path, e)))?;
+    let (appenders, errors) = raw.appenders_lossy(&Deserializers::default());
+    for e in errors {
+        eprintln!("Log config FILE '{}': {}", path, e);
+    }
+    let root = raw.root();
+    let root = Root::builder()
+        .appenders(root.appenders().to_vec())
+        .build(level.unwrap_or_else(|| root.level()));
+    Config::builder()
+        .appenders(appenders)
+        .loggers(raw.loggers())
+        .build(root)
+        .map_err(|e| ChordError::InvalidArgument(format!("log config FILE '{}': {}", path, e)))
+}
+
+/// Built-in configuration: all records go to `log/node-<id>-<port>.log`, warnings and errors to
+/// `log/error-<id>-<port>.log`. The console depends on the frontend: a daemon writes JSON lines for
+/// log collectors, the dashboard owns the terminal and the transcript of a script is printed to stdout
+fn default_config(options: &LogOptions, frontend: &Frontend, id: &BigInt, port: u16) -> Result<Config, ChordError> {
+    let encoder = || -> Box<dyn Encode> {
+        if options.json {
+            Box::new(JsonLineEncoder { node_id: id.to_string() })
+        } else {
+            Box::new(PatternEncoder::new(PLAIN_PATTERN))
+        }
+    };
+    let node_file = rolling_file(&format!("log/node-{}-{}.log", id, port), encoder())?;
+    let error_file = rolling_file(&format!("log/error-{}-{}.log", id, port), encoder())?;
+    let mut builder = Config::builder()
+        .appender(Appender::builder().build("node", Box::new(node_file)))
+        .appender(Appender::builder()
+            .filter(Box::new(ThresholdFilter::new(LevelFilter::Warn)))
+            .build("error", Box::new(error_file)));
+    let mut root = Root::builder().appender("node").appender("error");
+
+    let console = match frontend {
+        Frontend::Shell => Some((ConsoleAppender::builder()
+            .encoder(Box::new(PatternEncoder::new(CONSOLE_PATTERN)))
+            .build(), None)),
+        Frontend::Daemon => Some((ConsoleAppender::builder()
+            .encoder(Box::new(JsonLineEncoder { node_id: id.to_string() }))
+            .build(), None)),
+        Frontend::Script(_) => Some((ConsoleAppender::builder()
+            .target(Target::Stderr)
+            .encoder(Box::new(PatternEncoder::new(PLAIN_PATTERN)))
+            .build(), Some(LevelFilter::Warn))),
+        Frontend::Dashboard => None,
+    };
+    if let Some((console, threshold)) = console {
+        let mut appender = Appender::builder();
+        if let Some(threshold) = threshold {
+            appender = appender.filter(Box::new(ThresholdFilter::new(threshold)));
+        }
+        builder = builder.appender(appender.build("console", Box::new(console)));
+        root = root.appender("console");
+    }
+
+    builder.build(root.build(options.level.unwrap_or(LevelFilter::Info)))
+        .map_err(|e| ChordError::InvalidArgument(format!("log config: {}", e)))
+}
+
+fn rolling_file(path: &str, encoder: Box<dyn Encode>) -> Result<RollingFileAppender, ChordError> {
+    let policy = CompoundPolicy::new(Box::new(SizeTrigger::new(chord::LOG_FILE_SIZE_LIMIT)), Box::new(DeleteRoller::new()));
+    Ok(RollingFileAppender::builder()
+        .append(true)
+        .encoder(encoder)
+        .build(path, Box::new(policy))?)
+}
+
+/// Sets the id of the node for the records logged by the current thread, it changes with a salted id
+pub fn set_node_id(id: &BigInt) {
+    log_mdc::insert("node_id", id.to_string());
+}
+
+/// Adds the request id and message type of a message to the records logged by the current thread,
+/// until the context is dropped
+pub struct MessageContext {
+    _guard: ExtendGuard,
+}
+
+impl MessageContext {
+    pub fn enter(message: &Message) -> MessageContext {
+        let mut entries = vec![("message_type", message.get_type().to_string())];
+        match message {
+            Message::RequestMessage { request_id, .. } | Message::ResponseMessage { request_id, .. }
+            if *request_id != 0 => entries.push(("request_id", request_id.to_string())),
+            _ => {}
+        }
+        MessageContext { _guard: log_mdc::extend_scoped(entries) }
+    }
+}
+
+/// Writes every record as one JSON object per line, with the node id, request id and message
+/// type of the logging thread as fields of their own
+#[derive(Debug)]
+struct JsonLineEncoder {
+    /// Id of the node if the thread did not set one
+    node_id: String,
+}
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    time: String,
+    level: String,
+    node_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thread: Option<&'a str>,
+    target: &'a str,
+    message: String,
+}
+
+impl Encode for JsonLineEncoder {
+    fn encode(&self, w: &mut dyn Write, record: &Record) -> Result<(), Box<dyn Error + Sync + Send>> {
+        let thread = ::std::thread::current();
+        let line = JsonLine {
+            time: Local::now().to_rfc3339(),
+            level: record.level().to_string(),
+            node_id: log_mdc::get("node_id", |id| id.map(String::from)).unwrap_or_else(|| self.node_id.clone()),
+            request_id: log_mdc::get("request_id", |id| id.and_then(|id| id.parse::<u64>().ok())),
+            message_type: log_mdc::get("message_type", |message_type| message_type.map(String::from)),
+            thread: thread.name(),
+            target: record.target(),
+            message: record.args().to_string(),
+        };
+        serde_json::to_writer(&mut *w, &line)?;
+        w.write_all(b"\n")?;
+        Ok(())
+    }
+}
@@ -3,9 +3,12 @@ extern crate clap;
 extern crate crypto;
 extern crate futures;
 extern crate get_if_addrs;
+extern crate linefeed;
 #[macro_use]
 extern crate log;
 extern crate log4rs;
+extern crate log_mdc;
+extern crate net2;
 extern crate num;
 extern crate num_bigint;
 #[macro_use]
@@ -14,44 +17,65 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate serde_yaml;
 extern crate signal_hook;
+extern crate termion;
 extern crate tokio;
 
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::process;
+use std::time::Duration;
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use log::LevelFilter;
+use log4rs::Handle;
+use num_bigint::BigInt;
+
+use error::ChordError;
+use logging::LogOptions;
 
 mod input;
 mod print;
+mod script;
+mod snapshot;
 
+mod actor;
+mod admin;
+mod batch;
 mod chord;
+mod clock;
+mod dashboard;
+mod discovery;
+mod error;
+mod failure_detector;
 mod fingertable;
+mod lifecycle;
+mod logging;
 mod node;
+mod operation;
 mod storage;
 
 mod network;
+mod pending_request;
 mod protocols;
+mod traffic;
 
 fn main() {
-    // Init logger
-    log4rs::init_file("config/log4rs.yaml", Default::default()).unwrap();
-    debug!("Booting...");
-
-    // Find 'en0' interface on macOS, extract Ip4Addr and add it CLI help
-    let interfaces: Vec<get_if_addrs::Interface> = get_if_addrs::get_if_addrs().unwrap();
-    let interface_option = interfaces
-        .into_iter()
-        .find(|i| i.name == "en0" && i.addr.ip().is_ipv4());
-    let local_ip4addr = if let Some(interface) = interface_option {
+    // Find the first external interface (preferring 'en0' on macOS and IPv4), extract IpAddr and add it CLI help
+    let mut interfaces: Vec<get_if_addrs::Interface> = get_if_addrs::get_if_addrs().unwrap_or_default();
+    interfaces.retain(|i| !i.is_loopback());
+    interfaces.sort_by_key(|i| (i.name != "en0", !i.addr.ip().is_ipv4()));
+    let local_ipaddr = if let Some(interface) = interfaces.first() {
         interface.addr.ip().to_string()
     } else {
-        "<en0 not found>".to_string()
+        "<no external interface found>".to_string()
     };
-    let ip4addr_help = format!("Sets the ip address to use (e.g. {})", local_ip4addr);
+    let ipaddr_help = format!("Sets the IPv4 or IPv6 address to use (e.g. {})", local_ipaddr);
 
     // CLI requires static string
-    let ip4addr_help_slice = &ip4addr_help[..];
-    debug!("en0 interface IP4ADDR is: {}", local_ip4addr);
+    let ipaddr_help_slice = &ipaddr_help[..];
 
     // CLI incl. required arguments
     let matches = App::new("hll_rust_chord")
@@ -59,13 +83,13 @@ fn main() {
         .author("Andreas Ellwanger, Timo Erdelt and Andreas Griesbeck")
         .about("High level languages: Rust - Group project (2018/2019)")
         .arg(
-            Arg::with_name("ip4_addr")
+            Arg::with_name("ip_addr")
                 .short("i")
                 .long("ipaddr")
-                .value_name("IP4ADDR")
-                .help(ip4addr_help_slice)
+                .value_name("IPADDR")
+                .help(ipaddr_help_slice)
                 .takes_value(true)
-                .required(true)
+                .required_unless("advertise")
                 .index(1),
         )
         .arg(
@@ -73,64 +97,287 @@ fn main() {
                 .short("p")
                 .long("port")
                 .value_name("PORT")
-                .help("Sets the port to use")
+                .help("Sets the port to use, 0 picks a free port")
                 .takes_value(true)
-                .required(true)
+                .required_unless("bind")
                 .index(2),
         )
         .arg(
             Arg::with_name("entry_point")
                 .short("j")
                 .long("join")
-                .value_name("IP4ADDR:PORT")
-                .help("Sets the node (entry point to an existing chord ring) to join")
+                .value_name("HOST:PORT")
+                .help("Sets the seed nodes (entry points to an existing chord ring) to join, tried in order")
                 .takes_value(true)
+                .multiple(true)
                 .required(false)
                 .index(3),
         )
+        .arg(
+            Arg::with_name("discover")
+                .short("d")
+                .long("discover")
+                .help("Announces the node on the local network and, without seed nodes, joins a discovered node")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("cluster")
+                .short("c")
+                .long("cluster")
+                .value_name("NAME")
+                .help("Sets the cluster name used for discovery (default: hll-chord)")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("bind")
+                .short("b")
+                .long("bind")
+                .value_name("IPADDR:PORT")
+                .help("Sets the address to listen on (default: [::]:PORT, dual stack), PORT may be left out then")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("advertise")
+                .short("a")
+                .long("advertise")
+                .value_name("IPADDR:PORT")
+                .help("Sets the address other nodes reach this node at, which determines the node ID (default: IPADDR:PORT, \
+                       or IPADDR and the bound port without PORT). Port 0 advertises the bound port. IPADDR may be left out then")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("join_timeout")
+                .short("t")
+                .long("join-timeout")
+                .value_name("SECONDS")
+                .help("Sets the time after which joining via the seed nodes is given up (default: 60)")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("salt_id")
+                .short("s")
+                .long("salt-id")
+                .help("Picks a salted node ID if the ID of the address is already taken in the chord ring")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("daemon")
+                .long("daemon")
+                .help("Runs without interactive shell, logging events only. Requires --admin")
+                .requires("admin")
+                .conflicts_with_all(&["dashboard", "script"])
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("dashboard")
+                .long("dashboard")
+                .help("Shows a full screen dashboard instead of the interactive shell")
+                .conflicts_with("script")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("script")
+                .long("script")
+                .value_name("FILE")
+                .help("Executes the commands of the file (- for stdin) once joined, prints a JSON transcript and terminates")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("admin")
+                .long("admin")
+                .value_name("IPADDR:PORT")
+                .help("Sets the address of the admin socket to control the node with JSON requests")
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("log_level")
+                .long("log-level")
+                .value_name("LEVEL")
+                .help("Sets the log level (default: info)")
+                .possible_values(&["off", "error", "warn", "info", "debug", "trace"])
+                .takes_value(true)
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("log_json")
+                .long("log-json")
+                .help("Writes the log files as JSON lines with node id, request id and message type")
+                .conflicts_with("log_config")
+                .required(false),
+        )
+        .arg(
+            Arg::with_name("log_config")
+                .long("log-config")
+                .value_name("FILE")
+                .help("Sets a log4rs YAML file to use instead of config/log4rs.yaml, ${id} and ${port} are replaced")
+                .takes_value(true)
+                .required(false),
+        )
         .get_matches();
 
+    // Init logger on the console, it is configured for the node once its id and port are known
+    let log_level = matches.value_of("log_level").map(|level| level.parse::<LevelFilter>().unwrap());
+    let log_handle = match logging::init(log_level) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Unable to initialize logger: {}", e);
+            process::exit(1);
+        }
+    };
+    debug!("Booting...");
+    debug!("External interface IPADDR is: {}", local_ipaddr);
+
+    if let Err(e) = run(&matches, log_handle) {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(matches: &ArgMatches, log_handle: Handle) -> Result<(), ChordError> {
     // Validate, parse CLI arguments
-    let ip4_addr = match matches.value_of("ip4_addr").unwrap().parse::<Ipv4Addr>() {
-        Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
+    let Positionals { ip_addr, port, seeds: seed_args } = split_positionals(matches)?;
+    debug!("ip_addr: {:?}", ip_addr);
+    debug!("port: {:?}", port);
+    let bind_addr = match matches.value_of("bind") {
+        Some(bind_arg) => bind_arg.parse::<SocketAddr>()
+            .map_err(|e| ChordError::InvalidArgument(format!("bind IPADDR:PORT '{}': {}", bind_arg, e)))?,
+        None => SocketAddr::new(chord::LISTENING_ADDRESS, port.unwrap()),
     };
-    debug!("ip4_addr: {}", ip4_addr);
-    let port = match matches.value_of("port").unwrap().parse::<i32>() {
-        Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
+    let advertise_addr = match matches.value_of("advertise") {
+        Some(advertise_arg) => advertise_arg.parse::<SocketAddr>()
+            .map_err(|e| ChordError::InvalidArgument(format!("advertise IPADDR:PORT '{}': {}", advertise_arg, e)))?,
+        // Port 0 advertises the bound port
+        None => SocketAddr::new(ip_addr.unwrap(), port.unwrap_or(0)),
     };
-    debug!("port: {}", port);
-    let listening_ip = match format!("{}:{}", ip4_addr, port).parse::<SocketAddr>() {
-        Ok(m) => m,
-        Err(f) => panic!(f.to_string()),
+    if advertise_addr.ip().is_unspecified() {
+        return Err(ChordError::InvalidArgument(format!(
+            "{} can't be reached by other nodes, set a reachable address with --advertise", advertise_addr.ip())));
+    }
+
+    let frontend = if matches.is_present("daemon") {
+        chord::Frontend::Daemon
+    } else if matches.is_present("dashboard") {
+        chord::Frontend::Dashboard
+    } else if let Some(script) = matches.value_of("script") {
+        if script != "-" && !Path::new(script).is_file() {
+            return Err(ChordError::InvalidArgument(format!("script FILE '{}': no such file", script)));
+        }
+        chord::Frontend::Script(script.to_string())
+    } else {
+        chord::Frontend::Shell
+    };
+
+    // Bind first, the actual port of an ephemeral listener is part of the advertised address
+    let listener = network::bind_listener(bind_addr)?;
+    let bound_addr = listener.local_addr()?;
+    debug!("bound_addr: {}", bound_addr);
+    let listening_ip = if advertise_addr.port() == 0 {
+        chord::canonical_addr(SocketAddr::new(advertise_addr.ip(), bound_addr.port()))
+    } else {
+        chord::canonical_addr(advertise_addr)
     };
     debug!("listening_ip: {}", listening_ip);
 
-    // Join existing chord ring, or create new chord ring as first node
-    if matches.is_present("entry_point") {
-        let entry_point = match matches
-            .value_of("entry_point")
-            .unwrap()
-            .parse::<SocketAddr>()
-        {
-            Ok(m) => m,
-            Err(f) => panic!(f.to_string()),
-        };
-        debug!("entry_point: {}", entry_point);
-        if listening_ip != entry_point {
-            let node_handle = chord::spawn_node(listening_ip, port, Some(entry_point));
-            node_handle.join().expect("node_handle.join() failed");
-        } else {
-            panic!(
-                "listening_ip != entry_point = {}",
-                listening_ip != entry_point
-            );
+    // Daemons log events only. The dashboard owns the terminal and the transcript of a script is
+    // printed to stdout, so they log to files
+    let log_options = LogOptions {
+        level: matches.value_of("log_level").map(|level| level.parse::<LevelFilter>().unwrap()),
+        json: matches.is_present("log_json"),
+        config: matches.value_of("log_config").map(String::from),
+    };
+    logging::configure(&log_handle, &log_options, &frontend, &chord::create_node_id(listening_ip), listening_ip.port())?;
+    let log_frontend = frontend.clone();
+    let on_id_changed = Box::new(move |id: &BigInt| {
+        // Name the log files by the salted id from now on
+        if let Err(e) = logging::configure(&log_handle, &log_options, &log_frontend, id, listening_ip.port()) {
+            error!("Unable to configure logging for id #{}: {}", id, e);
         }
+    });
+
+    let salt_id = matches.is_present("salt_id");
+
+    let join_timeout = match matches.value_of("join_timeout") {
+        Some(join_timeout_arg) => Duration::from_secs(join_timeout_arg.parse::<u64>()
+            .map_err(|e| ChordError::InvalidArgument(format!("SECONDS '{}': {}", join_timeout_arg, e)))?),
+        None => chord::JOIN_TIMEOUT_INTERVAL,
+    };
+
+    let cluster = if matches.is_present("discover") {
+        Some(matches.value_of("cluster").unwrap_or(chord::DEFAULT_CLUSTER_NAME).to_string())
     } else {
-        let first_node_handle = chord::spawn_node(listening_ip, port, None);
-        first_node_handle
-            .join()
-            .expect("first_node_handle.join() failed");
+        None
+    };
+
+    // Join existing chord ring, or create new chord ring as first node
+    let mut seeds: Vec<String> = Vec::new();
+    if !seed_args.is_empty() {
+        seeds = seed_args;
+        for seed in &seeds {
+            chord::validate_seed(seed)?;
+            if seed.parse::<SocketAddr>().ok().map(chord::canonical_addr) == Some(listening_ip) {
+                return Err(ChordError::InvalidArgument(format!(
+                    "entry point {} must not be the address of the node itself", seed)));
+            }
+        }
+    } else if let Some(cluster) = &cluster {
+        if let Some(seed) = discovery::discover_seed(cluster, listening_ip)? {
+            seeds.push(seed.to_string());
+        }
     }
+
+    let admin = match matches.value_of("admin") {
+        Some(admin_arg) => {
+            let admin_addr = admin_arg.parse::<SocketAddr>()
+                .map_err(|e| ChordError::InvalidArgument(format!("admin IPADDR:PORT '{}': {}", admin_arg, e)))?;
+            Some(net::TcpListener::bind(admin_addr)?)
+        }
+        None => None,
+    };
+    debug!("seeds: {:?}", seeds);
+    let options = chord::NodeOptions { seeds, join_timeout, salt_id, cluster, admin, frontend, on_id_changed };
+    let node_handle = chord::spawn_node(listener, listening_ip, options);
+    node_handle.join().expect("node_handle.join() failed");
+    Ok(())
+}
+
+/// Positional arguments of the command line
+struct Positionals {
+    ip_addr: Option<IpAddr>,
+    port: Option<u16>,
+    seeds: Vec<String>,
+}
+
+/// Splits the positional arguments into IPADDR, PORT and seed nodes. IPADDR may be left out
+/// with `--advertise` and PORT with `--bind`, the following seed nodes move up then.
+fn split_positionals(matches: &ArgMatches) -> Result<Positionals, ChordError> {
+    let mut args = ["ip_addr", "port"].iter()
+        .filter_map(|name| matches.value_of(name))
+        .chain(matches.values_of("entry_point").into_iter().flatten())
+        .peekable();
+
+    let ip_addr = match args.peek() {
+        Some(arg) if !matches.is_present("advertise") || arg.parse::<IpAddr>().is_ok() => {
+            let arg = args.next().unwrap();
+            Some(arg.parse::<IpAddr>()
+                .map_err(|e| ChordError::InvalidArgument(format!("IPADDR '{}': {}", arg, e)))?)
+        }
+        _ if !matches.is_present("advertise") => return Err(ChordError::InvalidArgument("IPADDR is missing".to_string())),
+        _ => None,
+    };
+    let port = match args.peek() {
+        Some(arg) if !matches.is_present("bind") || arg.parse::<u16>().is_ok() => {
+            let arg = args.next().unwrap();
+            Some(arg.parse::<u16>()
+                .map_err(|e| ChordError::InvalidArgument(format!("PORT '{}': {}", arg, e)))?)
+        }
+        _ if !matches.is_present("bind") => return Err(ChordError::InvalidArgument("PORT is missing".to_string())),
+        _ => None,
+    };
+    Ok(Positionals { ip_addr, port, seeds: args.map(String::from).collect() })
 }
@@ -1,62 +1,77 @@
-use std::{process, str, thread};
-use std::io::{BufWriter, Write};
+use std::{str, thread};
+use std::io::{BufWriter, Read, Write};
 use std::io::BufReader;
 use std::net;
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
 use std::thread::JoinHandle;
 
 use futures::{Future, Stream};
-use num_bigint::BigInt;
+use net2::TcpBuilder;
 use tokio::io;
 use tokio::net::TcpListener;
+use tokio::reactor::Handle;
+use tokio::util::FutureExt;
 
+use super::actor::Command;
 use super::chord;
+use super::error::ChordError;
+use super::logging::MessageContext;
 use super::node::*;
 use super::protocols::*;
+use super::traffic::{ConnectionLimiter, RateLimiter, Rejection, TrafficStats};
 
 pub fn send_kill(target: SocketAddr) -> JoinHandle<()> {
-    let msg = Message::Kill;
-    send_string_to_socket(target, serde_json::to_string(&msg).unwrap())
+    send_message(target, Message::Kill)
 }
 
-pub fn send_response(sender: OtherNode, target:SocketAddr, response: Response) -> JoinHandle<()> {
-    let msg = Message::ResponseMessage { sender, response };
-    send_string_to_socket(target, serde_json::to_string(&msg).unwrap())
+/// Sends a request without tracking it, the node tracks its own requests with `Node::send_request`
+pub fn send_request(sender: OtherNode, target: SocketAddr, request: Request) -> JoinHandle<()> {
+    send_message(target, Message::RequestMessage { sender, request_id: 0, request })
 }
 
-pub fn send_request(sender: OtherNode, target:SocketAddr, request: Request) -> JoinHandle<()> {
-    let msg = Message::RequestMessage { sender, request };
-    send_string_to_socket(target, serde_json::to_string(&msg).unwrap())
-}
-
-fn send_string_to_socket(addr: SocketAddr, msg: String) -> JoinHandle<()> {
+pub fn send_message(addr: SocketAddr, msg: Message) -> JoinHandle<()> {
     let builder = thread::Builder::new().name("Send".to_string());
     builder.spawn(move || {
-        match net::TcpStream::connect(addr) {
-            Ok(stream) => {
-                let mut writer = BufWriter::new(stream);
-                writer.write_all(msg.as_bytes()).unwrap();
-                debug!("Sent msg: {}", msg);
-            }
-            Err(e) => {
-                debug!("Unable to send msg to {} - Failed to connect: {}", addr, e);
-            }
+        let _context = MessageContext::enter(&msg);
+        if let Err(e) = write_message_to_socket(addr, &msg) {
+            debug!("Unable to send msg to {}: {}", addr, e);
         }
     }).unwrap()
 }
 
-pub fn check_alive(addr: SocketAddr, sender: OtherNode) -> bool {
-    match net::TcpStream::connect_timeout(&addr, chord::PING_TIMEOUT_INTERVAL) {
-        Ok(stream) => {
-            let msg = serde_json::to_string(&Message::Ping { sender }).unwrap();
-            let mut writer = BufWriter::new(stream);
-            writer.write_all(msg.as_bytes()).unwrap();
-            true
-        }
+fn write_message_to_socket(addr: SocketAddr, msg: &Message) -> Result<(), ChordError> {
+    let msg_string = serde_json::to_string(msg)?;
+    let stream = net::TcpStream::connect(addr)?;
+    let mut writer = BufWriter::new(stream);
+    writer.write_all(msg_string.as_bytes())?;
+    debug!("Sent msg: {}", msg_string);
+    Ok(())
+}
+
+fn parse_message(buf: &[u8]) -> Result<Message, ChordError> {
+    let msg_string = str::from_utf8(buf)?;
+    Ok(serde_json::from_str(msg_string)?)
+}
+
+/// Binds the listener of the node. The unspecified IPv6 address binds a dual stack listener for IPv4
+/// and IPv6 peers, falling back to IPv4 if the host has no IPv6. Port 0 binds an ephemeral port.
+pub fn bind_listener(bind_addr: SocketAddr) -> Result<net::TcpListener, ChordError> {
+    if bind_addr.ip() != chord::LISTENING_ADDRESS {
+        return Ok(net::TcpListener::bind(bind_addr)?);
+    }
+    let dual_stack = TcpBuilder::new_v6().and_then(|builder| {
+        builder.only_v6(false)?;
+        builder.reuse_address(true)?;
+        builder.bind(bind_addr)?;
+        builder.listen(1024)
+    });
+    match dual_stack {
+        Ok(listener) => Ok(listener),
         Err(e) => {
-            debug!("Check alive timeouted: {:?}", e);
-            false
+            warn!("Unable to listen on IPv6 ({}), accepting IPv4 peers only", e);
+            Ok(net::TcpListener::bind(SocketAddr::new(chord::LISTENING_ADDRESS_V4, bind_addr.port()))?)
         }
     }
 }
@@ -65,70 +80,79 @@ pub fn check_alive(addr: SocketAddr, sender: OtherNode) -> bool {
 // nc 127.0.0.1 34254
 // can be killed by sending "Kill" (with apostrophes)
 // afterwards every message will be echoed in the console by handle_request
-pub fn start_listening_on_socket(node_arc: Arc<Mutex<Node>>, port: i32, id: BigInt) -> Result<(), Box<std::error::Error>> {
-    let listen_ip = format!("{}:{}", chord::LISTENING_ADDRESS, port)
-        .parse::<SocketAddr>()
-        .unwrap();
+/// Accepts inbound messages and hands them over to the node actor
+pub fn start_listening_on_socket(listener: net::TcpListener, actor: Sender<Command>, stats: Arc<TrafficStats>) -> Result<(), ChordError> {
+    let listen_ip = listener.local_addr()?;
+    let listener = TcpListener::from_std(listener, &Handle::default())?;
 
-    let listener = TcpListener::bind(&listen_ip).unwrap();
+    debug!("Starting to listen on socket: {}", listen_ip);
 
-    debug!("[Node #{}] Starting to listen on socket: {}", id.clone(), listen_ip);
+    let connection_limiter = ConnectionLimiter::new();
+    let rate_limiter = RateLimiter::new();
 
     let server = listener.incoming().for_each(move |socket| {
         //debug!("[Node #{}] accepted socket; addr={:?}", id, socket.peer_addr()?);
 
+        let permit = match connection_limiter.try_acquire() {
+            Some(permit) => permit,
+            None => {
+                stats.record(Rejection::ConnectionLimit);
+                debug!("Rejecting connection, too many inbound connections");
+                return Ok(());
+            }
+        };
+        if let Ok(peer_addr) = socket.peer_addr() {
+            if !rate_limiter.allow(chord::canonical_ip(peer_addr.ip())) {
+                stats.record(Rejection::RateLimit);
+                debug!("Rejecting connection from {}, rate limit exceeded", peer_addr.ip());
+                return Ok(());
+            }
+        }
+
         let buf = vec![];
-        let buf_reader = BufReader::new(socket);
+        // Read at most one byte more than allowed to detect oversized messages
+        let buf_reader = BufReader::new(socket.take(chord::MAX_MESSAGE_SIZE as u64 + 1));
 
-        let arc_clone = node_arc.clone();
+        let actor_clone = actor.clone();
+        let stats_clone = stats.clone();
 
         let connection = io::read_until(buf_reader, b'\n', buf)
-            .and_then(move |(_socket, buf)| {
-                let msg_string = str::from_utf8(&buf).unwrap();
-                let message = serde_json::from_str(msg_string).unwrap();
-                //info!("Look at me: {:?}",serde_json::to_string(&Message::Kill{}).unwrap());
-                let mut node = arc_clone.lock().unwrap();
-                match message {
-                    Message::Kill => {
-                        info!("Got kill message, shutting down...");
-                        let node_clone = node.clone();
-                        drop(node);
-
-                        let handle_opt = node_clone.clone().graceful_shutdown();
-                        if let Some(handle) = handle_opt {
-                            handle.join().expect("handle_graceful_shutdown failed");
-                        }
-                        process::exit(0);
-                    }
-                    Message::Ping { sender } => {
-                        debug!("Got pinged from Node #{}", sender.get_id());
-                        Ok(())
+            .timeout(chord::READ_TIMEOUT_INTERVAL)
+            .then(move |result| {
+                match result {
+                    Ok((_socket, ref buf)) if buf.len() > chord::MAX_MESSAGE_SIZE => {
+                        stats_clone.record(Rejection::Oversized);
+                        warn!("Discarding message, it exceeds {} bytes", chord::MAX_MESSAGE_SIZE);
                     }
-                    Message::RequestMessage { sender, request } => {
-                        debug!("[Node #{}] Got request from Node #{}: {:?}", node.get_id().clone(), sender.get_id(), request.clone());
-                        let response_option = node.process_incoming_request(request);
-                        let node_as_other_node = node.to_other_node();
-                        drop(node);
-                        if let Some(response) = response_option {
-                            send_response(node_as_other_node, *sender.get_ip_addr(), response);
+                    Ok((_socket, buf)) => match parse_message(&buf) {
+                        Ok(message) => {
+                            if actor_clone.send(Command::Incoming(Box::new(message))).is_err() {
+                                debug!("Discarding message, node is not running anymore");
+                            }
+                        }
+                        Err(e) => {
+                            stats_clone.record(Rejection::Malformed);
+                            warn!("Discarding message: {}", e);
                         }
-                        Ok(())
+                    },
+                    Err(ref e) if e.is_elapsed() => {
+                        stats_clone.record(Rejection::Timeout);
+                        debug!("Discarding connection, no message received within {:?}", chord::READ_TIMEOUT_INTERVAL);
                     }
-                    Message::ResponseMessage { sender, response } => {
-                        debug!("[Node #{}] Got response from Node #{}: {:?}", node.get_id().clone(), sender.get_id(), response.clone());
-                        node.process_incoming_response(response);
-                        drop(node);
-                        Ok(())
+                    Err(e) => {
+                        debug!("Unable to read message: {:?}", e);
                     }
                 }
-            })
-            .then(|_| Ok(())); // Just discard the socket and buffer
+                drop(permit);
+                Ok(())
+            }); // Just discard the socket and buffer
 
         // Spawn a new task that processes the socket:
         tokio::spawn(connection);
 
         Ok(())
-    }).map_err(|e| println!("failed to accept socket; error = {:?}", e));
+    }).map_err(|e| error!("failed to accept socket; error = {:?}", e));
     tokio::run(server);
     Ok(())
-}
\ No newline at end of file
+}
+
@@ -1,14 +1,27 @@
+use std::collections::HashMap;
+use std::iter;
+use std::mem;
 use std::net::SocketAddr;
 use std::str;
-use std::thread::JoinHandle;
+use std::sync::Arc;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 use num_bigint::{BigInt, Sign};
 
+use super::batch::PendingBatch;
 use super::chord;
-use super::fingertable::FingerTable;
-use super::network;
+use super::clock::{Clock, SystemClock};
+use super::error::ChordError;
+use super::failure_detector::FailureDetector;
+use super::fingertable::{FingerTable, get_finger_id};
+use super::lifecycle::NodeState;
+use super::operation::{FoundKey, OperationReply, OperationResult, StoredKey};
+use super::pending_request::PendingRequest;
 use super::protocols::*;
-use super::storage::{DHTEntry, Storage};
+use super::snapshot::{ExportEvent, ImportEvent, PendingExport, PendingImport};
+use super::storage::{DHTEntry, Lease, LeaseTable, Storage, Tombstone, WriteCondition};
+use super::traffic::TrafficStats;
 
 /// Simple representation of an external node in the network
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -31,8 +44,7 @@ impl OtherNode {
     }
 }
 
-/// Complete representation of internal node
-#[derive(Clone)]
+/// Complete representation of internal node, owned by the node actor
 pub struct Node {
     id: BigInt,
     ip_addr: SocketAddr,
@@ -40,12 +52,38 @@ pub struct Node {
     predecessor: Option<OtherNode>,
     successor_list: Vec<OtherNode>,
     storage: Storage,
-    joined: bool,
+    state: NodeState,
+    pending_stores: HashMap<u64, PendingBatch<(String, u64)>>,
+    pending_finds: HashMap<u64, PendingBatch<(BigInt, Option<DHTEntry>)>>,
+    pending_requests: HashMap<u64, PendingRequest>,
+    next_request_id: u64,
+    // Frontends waiting for the result of a user request, by operation id
+    operations: HashMap<u64, OperationReply>,
+    next_operation_id: u64,
+    failure_detector: FailureDetector,
+    traffic_stats: Arc<TrafficStats>,
+    next_finger: usize,
+    salt_id_on_collision: bool,
+    id_salt: u32,
+    // Entry points that were used to join the ring
+    bootstrap_peers: Vec<SocketAddr>,
+    // Peers that have not been asked yet while rejoining after losing all successors
+    rejoin_candidates: Option<Vec<SocketAddr>>,
+    // Keys watched by this node with the last known version (0: absent), `None` until the owner answered
+    watched_keys: HashMap<BigInt, (String, Option<u64>)>,
+    // Topics subscribed by this node, and whether the rendezvous node confirmed the subscription
+    subscribed_topics: HashMap<BigInt, (String, bool)>,
+    // Ring walk of the running export and entries of the running import
+    pending_export: Option<PendingExport>,
+    pending_import: Option<PendingImport>,
+    // Effects queued while processing the current input
+    outbox: Vec<Effect>,
+    clock: Box<dyn Clock>,
 }
 
 /// `Node` implementation
 impl Node {
-    pub fn new(node_ip_addr: SocketAddr) -> Node {
+    pub fn new(node_ip_addr: SocketAddr, bootstrap_peers: Vec<SocketAddr>, salt_id_on_collision: bool) -> Node {
         let id = chord::create_node_id(node_ip_addr);
         Node {
             id: id.clone(),
@@ -54,7 +92,26 @@ impl Node {
             predecessor: None,
             successor_list: Vec::with_capacity(chord::SUCCESSORLIST_SIZE),
             storage: Storage::new(),
-            joined: false,
+            state: NodeState::Joining,
+            pending_stores: HashMap::new(),
+            pending_finds: HashMap::new(),
+            pending_requests: HashMap::new(),
+            next_request_id: 1,
+            operations: HashMap::new(),
+            next_operation_id: 1,
+            failure_detector: FailureDetector::new(),
+            traffic_stats: Arc::new(TrafficStats::new()),
+            next_finger: 1,
+            salt_id_on_collision,
+            id_salt: 0,
+            bootstrap_peers,
+            rejoin_candidates: None,
+            watched_keys: HashMap::new(),
+            subscribed_topics: HashMap::new(),
+            pending_export: None,
+            pending_import: None,
+            outbox: Vec::new(),
+            clock: Box::new(SystemClock),
         }
     }
 
@@ -68,10 +125,36 @@ impl Node {
             predecessor: Some(OtherNode::new(id, node_ip_addr)),
             successor_list: vec![successor],
             storage: Storage::new(),
-            joined: true,
+            state: NodeState::Active,
+            pending_stores: HashMap::new(),
+            pending_finds: HashMap::new(),
+            pending_requests: HashMap::new(),
+            next_request_id: 1,
+            operations: HashMap::new(),
+            next_operation_id: 1,
+            failure_detector: FailureDetector::new(),
+            traffic_stats: Arc::new(TrafficStats::new()),
+            next_finger: 1,
+            salt_id_on_collision: false,
+            id_salt: 0,
+            bootstrap_peers: Vec::new(),
+            rejoin_candidates: None,
+            watched_keys: HashMap::new(),
+            subscribed_topics: HashMap::new(),
+            pending_export: None,
+            pending_import: None,
+            outbox: Vec::new(),
+            clock: Box::new(SystemClock),
         }
     }
 
+    /// Replaces the system clock by a manual clock in tests
+    #[cfg(test)]
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Node {
+        self.clock = clock;
+        self
+    }
+
     pub fn get_id(&self) -> &BigInt {
         &self.id
     }
@@ -96,7 +179,7 @@ impl Node {
 
         // Redistribute keys, that I am not responsible for anymore
         if let Some(pre) = predecessor {
-            self.check_redistribute_dht_keys(&pre.id)
+            self.check_redistribute_dht_keys(&pre)
         }
     }
 
@@ -108,8 +191,51 @@ impl Node {
         &self.storage
     }
 
+    /// Whether the node is part of the ring, a failed or leaving node is not
     pub fn is_joined(&self) -> bool {
-        self.joined
+        self.state.is_member()
+    }
+
+    pub fn get_state(&self) -> NodeState {
+        self.state
+    }
+
+    /// Moves the node to the next lifecycle state, if the lifecycle allows it
+    fn transition(&mut self, next: NodeState) -> Result<(), ChordError> {
+        if !self.state.can_transition_to(next) {
+            return Err(ChordError::InvalidTransition(self.state, next));
+        }
+        info!("[Node #{}] State changed from {} to {}", self.id, self.state, next);
+        self.storage.write_log_entry(format!("State changed from {} to {}", self.state, next));
+        self.state = next;
+        Ok(())
+    }
+
+    pub fn get_traffic_stats(&self) -> &Arc<TrafficStats> {
+        &self.traffic_stats
+    }
+
+    pub fn get_failure_detector(&self) -> &FailureDetector {
+        &self.failure_detector
+    }
+
+    /// Watches the predecessor and all nodes of the successor list and returns them, so they can be pinged
+    fn watch_peers(&mut self) -> Vec<OtherNode> {
+        let mut peers: Vec<OtherNode> = Vec::new();
+        for peer in self.successor_list.iter().chain(self.predecessor.iter()) {
+            if peer.id != self.id && !peers.iter().any(|p| p.id == peer.id) {
+                peers.push(peer.clone());
+            }
+        }
+        let peer_ids: Vec<BigInt> = peers.iter().map(|peer| peer.id.clone()).collect();
+        let now = self.clock.now();
+        self.failure_detector.watch(&peer_ids, now);
+        peers
+    }
+
+    /// Returns false if the failure detector suspects the node to have crashed
+    pub fn is_alive(&self, node: &OtherNode) -> bool {
+        node.id == self.id || self.failure_detector.is_available(&node.id, self.clock.now())
     }
 
     /// Converts internal representation of node to the simpler representation OtherNode
@@ -117,36 +243,667 @@ impl Node {
         OtherNode::new(self.id.clone(), self.ip_addr)
     }
 
-    pub fn update_successor_and_successor_list(&mut self, successor: OtherNode) {
+    /// Takes the effects that were queued while processing the last input
+    fn take_effects(&mut self) -> Vec<Effect> {
+        mem::take(&mut self.outbox)
+    }
+
+    fn send_message(&mut self, target: SocketAddr, message: Message) {
+        self.outbox.push(Effect::Send { target, message: Box::new(message) });
+    }
+
+    /// Processes a message received by the listener
+    pub fn handle_message(&mut self, message: Message) -> Vec<Effect> {
+        match message {
+            Message::Kill => {
+                info!("Got kill message, shutting down...");
+                return self.graceful_shutdown();
+            }
+            Message::Ping { sender, timestamp } => {
+                debug!("Got pinged from Node #{}", sender.get_id());
+                let pong = Message::Pong { sender: self.to_other_node(), timestamp };
+                self.send_message(sender.ip_addr, pong);
+            }
+            Message::Pong { sender, timestamp } => {
+                let rtt = Duration::from_millis(self.clock.now_as_millis().saturating_sub(timestamp));
+                debug!("Got pong from Node #{} (rtt: {:?})", sender.get_id(), rtt);
+                let now = self.clock.now();
+                self.failure_detector.heartbeat(sender.get_id(), rtt, now);
+            }
+            Message::RequestMessage { sender, request_id, request } => {
+                debug!("[Node #{}] Got request from Node #{}: {:?}", self.id, sender.get_id(), request);
+                let response_option = match self.process_incoming_request(&sender, request) {
+                    Ok(response_option) => response_option,
+                    Err(error) => {
+                        warn!("[Node #{}] Unable to process request from Node #{}: {}", self.id, sender.get_id(), error);
+                        Some(Response::Error { error })
+                    }
+                };
+                if let Some(response) = response_option {
+                    let msg = Message::ResponseMessage { sender: self.to_other_node(), request_id, response };
+                    self.send_message(sender.ip_addr, msg);
+                }
+            }
+            Message::ResponseMessage { sender, request_id, response } => {
+                debug!("[Node #{}] Got response from Node #{}: {:?}", self.id, sender.get_id(), response);
+                self.process_incoming_response(sender, request_id, response);
+            }
+        }
+        self.take_effects()
+    }
+
+    pub fn join(&mut self, entry_point: SocketAddr) -> Vec<Effect> {
+        self.send_join_request(entry_point);
+        self.take_effects()
+    }
+
+    fn send_join_request(&mut self, entry_point: SocketAddr) {
+        info!("Trying to join via {}...", entry_point);
+        let req = Request::FindSuccessor { id: self.id.clone() };
+        let msg = Message::RequestMessage { sender: self.to_other_node(), request_id: 0, request: req };
+        self.send_message(entry_point, msg);
+    }
+
+    /// Asks the first living successor for its predecessor, which is answered by a notify
+    pub fn stabilize(&mut self) -> Vec<Effect> {
+        debug!("Stabilize.............");
+        if self.rejoin_candidates.is_some() {
+            self.rejoin();
+            return self.take_effects();
+        }
+        if !self.state.is_member() {
+            info!("[Node #{}] Node is {}, going to sleep again", self.id, self.state);
+            return Vec::new();
+        }
+        let successor_option = self.successor_list.iter()
+            .find(|succ| {
+                let alive = self.is_alive(succ);
+                if !alive {
+                    debug!("Node is dead: {:?}", succ);
+                }
+                alive
+            })
+            .cloned();
+        match successor_option {
+            Some(succ) => {
+                self.send_request(succ.clone(), Request::GetPredecessor);
+                self.update_successor_and_successor_list(succ);
+            }
+            None => {
+                error!("[Node #{}] No functional successor found in successor list. Trying to rejoin...", self.id);
+                self.start_rejoin();
+            }
+        }
+        self.take_effects()
+    }
+
+    /// Collects the former fingers, the predecessor and the bootstrap peers,
+    /// which are asked one after another to let the node rejoin the ring
+    fn start_rejoin(&mut self) {
+        if let Err(e) = self.transition(NodeState::Failed) {
+            warn!("[Node #{}] {}", self.id, e);
+            return;
+        }
+        let mut candidates: Vec<SocketAddr> = Vec::new();
+        let former_fingers = (0..self.finger_table.length())
+            .map(|i| self.finger_table.get(i).get_node().ip_addr)
+            .chain(self.predecessor.iter().map(|pre| pre.ip_addr))
+            .filter(|addr| *addr != self.ip_addr);
+        for addr in former_fingers.chain(self.bootstrap_peers.iter().cloned()) {
+            if !candidates.contains(&addr) {
+                candidates.push(addr);
+            }
+        }
+        // Candidates are taken from the end
+        candidates.reverse();
+        if let Err(e) = self.transition(NodeState::Joining) {
+            warn!("[Node #{}] {}", self.id, e);
+        }
+        self.rejoin_candidates = Some(candidates);
+        self.rejoin();
+    }
+
+    /// Asks the next candidate for the successor, or continues as single node ring if none is left
+    fn rejoin(&mut self) {
+        match self.rejoin_candidates.as_mut().and_then(|candidates| candidates.pop()) {
+            Some(candidate) => self.send_join_request(candidate),
+            None => {
+                warn!("[Node #{}] No peer to rejoin found, continuing as single node ring", self.id);
+                let me = self.to_other_node();
+                self.finger_table = FingerTable::new_first(self.id.clone(), me.clone());
+                self.successor_list = vec![me.clone()];
+                self.predecessor = Some(me);
+                self.rejoin_candidates = None;
+                if let Err(e) = self.transition(NodeState::Active) {
+                    warn!("[Node #{}] {}", self.id, e);
+                }
+            }
+        }
+    }
+
+    /// Refreshes one finger per call, cycling through the finger table
+    pub fn fix_next_finger(&mut self) -> Vec<Effect> {
+        if !self.state.is_member() {
+            info!("[Node #{}] Node is {}, going to sleep again", self.id, self.state);
+            return Vec::new();
+        }
+        let index = self.next_finger;
+        let finger_id = get_finger_id(&self.id, index);
+        let req = Request::FindSuccessorFinger { index, finger_id };
+        let successor = self.get_successor();
+        self.send_request(successor, req);
+
+        self.next_finger = if index < chord::FINGERTABLE_SIZE - 1 {
+            index + 1
+        } else {
+            1
+        };
+        self.take_effects()
+    }
+
+    pub fn check_predecessor(&mut self) -> Vec<Effect> {
+        if !self.state.is_member() {
+            info!("[Node #{}] Node is {}, going to sleep again", self.id, self.state);
+            return Vec::new();
+        }
+        if let Some(predecessor) = self.predecessor.clone() {
+            if !self.is_alive(&predecessor) {
+                debug!("Predecessor Node #{} is dead (phi: {})", predecessor.get_id(),
+                       self.failure_detector.phi(predecessor.get_id(), self.clock.now()));
+                self.set_predecessor(None);
+            } else {
+                debug!("Predecessor Node #{} is alive", predecessor.get_id());
+            }
+        }
+        self.take_effects()
+    }
+
+    /// Pings the predecessor and the successor list, the pongs feed the failure detector
+    pub fn ping_peers(&mut self) -> Vec<Effect> {
+        for peer in self.watch_peers() {
+            let ping = Message::Ping { sender: self.to_other_node(), timestamp: self.clock.now_as_millis() };
+            self.send_message(peer.ip_addr, ping);
+        }
+        self.take_effects()
+    }
+
+    fn update_successor_and_successor_list(&mut self, successor: OtherNode) {
         //if self.finger_table.length() == 0  || &self.get_successor().id != &successor.id {
         self.finger_table.set_successor(successor.clone());
         let req = Request::GetSuccessorList;
-        network::send_request(self.to_other_node(),*successor.get_ip_addr(),req);
+        self.send_request(successor, req);
     }
 
-    pub fn graceful_shutdown(&self) -> Option<JoinHandle<()>> {
-        if self.joined && !self.storage.is_data_empty() {
-            info!("Initializing shutdown, moving keys...");
-            let req = Request::DHTTakeOverKeys { data: self.storage.get_data_as_vec().clone() };
-            info!("Shutting down.");
-            Some(network::send_request(self.to_other_node(), *self.get_successor().get_ip_addr(), req))
+    /// Hands all keys over to the successor. The node is draining until the successor
+    /// took them over or could not be reached, then it terminates the process.
+    pub fn graceful_shutdown(&mut self) -> Vec<Effect> {
+        if self.state == NodeState::Draining {
+            info!("[Node #{}] Still waiting for the successor to take over the keys", self.id);
+            return Vec::new();
+        }
+        let watches = self.storage.get_watches().get_as_vec();
+        let subscriptions = self.storage.get_subscriptions().get_as_vec();
+        let tombstones = self.storage.get_tombstones_as_vec();
+        let successor = self.get_successor();
+        if self.state == NodeState::Active && successor.id != self.id
+            && (!self.storage.is_data_empty() || !tombstones.is_empty() || !watches.is_empty() || !subscriptions.is_empty()) {
+            // Can't fail, active nodes may always start draining
+            let _ = self.transition(NodeState::Draining);
+            info!("Initializing shutdown, moving keys to node #{}...", successor.id);
+            let req = Request::DHTTakeOverKeys { data: self.storage.get_data_as_vec(), tombstones, watches, subscriptions };
+            self.send_request(successor, req);
         } else {
-            None
+            self.leave();
+        }
+        self.take_effects()
+    }
+
+    /// Terminates the process, once the keys are handed over
+    fn leave(&mut self) {
+        if let Err(e) = self.transition(NodeState::Leaving) {
+            warn!("[Node #{}] {}", self.id, e);
+        }
+        info!("Shutting down.");
+        self.outbox.push(Effect::Exit { code: 0 });
+    }
+
+    fn check_redistribute_dht_keys(&mut self, pre: &OtherNode) {
+        for (key, value) in self.storage.get_data_as_vec() {
+            if !chord::is_my_key(&self.id, pre.get_id(), &key) {
+                self.storage.remove_key(&key);
+                let req = Request::DHTStoreKey { data: (key, value) };
+                let me = self.to_other_node();
+                self.send_request(me, req);
+            }
+        }
+        // Keys only move to a new predecessor, which takes over their tombstones, watches and subscriptions as well
+        let tombstones = self.remove_foreign_tombstones(pre.get_id());
+        let watches = self.remove_foreign_leases(pre.get_id(), |storage| storage.get_watches_mut());
+        let subscriptions = self.remove_foreign_leases(pre.get_id(), |storage| storage.get_subscriptions_mut());
+        if (!tombstones.is_empty() || !watches.is_empty() || !subscriptions.is_empty()) && pre.id != self.id {
+            debug!("[Node #{}] Handing {} tombstones, {} watches and {} subscriptions over to node #{}",
+                   self.id, tombstones.len(), watches.len(), subscriptions.len(), pre.id);
+            let req = Request::DHTTakeOverKeys { data: Vec::new(), tombstones, watches, subscriptions };
+            let msg = Message::RequestMessage { sender: self.to_other_node(), request_id: 0, request: req };
+            self.send_message(pre.ip_addr, msg);
+        }
+    }
+
+    /// Removes the tombstones of keys this node is not responsible for anymore
+    fn remove_foreign_tombstones(&mut self, pre_id: &BigInt) -> Vec<(BigInt, Tombstone)> {
+        let mut tombstones = Vec::new();
+        for (key_id, _) in self.storage.get_tombstones_as_vec() {
+            if !chord::is_my_key(&self.id, pre_id, &key_id) {
+                tombstones.extend(self.storage.remove_tombstone(&key_id).map(|tombstone| (key_id, tombstone)));
+            }
+        }
+        tombstones
+    }
+
+    /// Removes the leases on ids this node is not responsible for anymore
+    fn remove_foreign_leases<F>(&mut self, pre_id: &BigInt, table: F) -> Vec<(BigInt, Lease)>
+        where F: Fn(&mut Storage) -> &mut LeaseTable {
+        let table = table(&mut self.storage);
+        let mut leases = Vec::new();
+        for id in table.get_ids() {
+            if !chord::is_my_key(&self.id, pre_id, &id) {
+                leases.extend(table.remove(&id).into_iter().map(|lease| (id.clone(), lease)));
+            }
+        }
+        leases
+    }
+
+    pub fn expire_keys(&mut self) -> Vec<Effect> {
+        for (key_id, key) in self.storage.remove_expired_keys() {
+            self.storage.write_log_entry(format!("Key '{}' expired", key));
+            debug!("[Node #{}] Key '{}' expired", self.id, key);
+            self.notify_watchers(&key_id);
+        }
+        let expired_watches = self.storage.get_watches_mut().remove_expired();
+        let expired_subscriptions = self.storage.get_subscriptions_mut().remove_expired();
+        if expired_watches > 0 || expired_subscriptions > 0 {
+            debug!("[Node #{}] {} watches and {} subscriptions expired", self.id, expired_watches, expired_subscriptions);
+        }
+        self.take_effects()
+    }
+
+    /// Starts watching a key, changes are reported in the storage log
+    pub fn watch_key(&mut self, key: String) -> Vec<Effect> {
+        let key_id = chord::create_id(&key);
+        self.watched_keys.insert(key_id.clone(), (key, None));
+        let me = self.to_other_node();
+        self.send_request(me, Request::DHTWatchKey { key_id });
+        self.take_effects()
+    }
+
+    /// Stops watching a key, the lease at the owner runs out since it is not renewed anymore
+    pub fn unwatch_key(&mut self, key: String) -> Vec<Effect> {
+        match self.watched_keys.remove(&chord::create_id(&key)) {
+            Some(_) => self.storage.write_log_entry(format!("Stopped watching key '{}'", key)),
+            None => self.storage.write_log_entry(format!("Key '{}' is not watched", key)),
+        }
+        self.take_effects()
+    }
+
+    /// Subscribes to a topic, published messages are reported in the storage log
+    pub fn subscribe(&mut self, topic: String) -> Vec<Effect> {
+        let topic_id = chord::create_id(&topic);
+        self.subscribed_topics.insert(topic_id.clone(), (topic, false));
+        let me = self.to_other_node();
+        self.send_request(me, Request::Subscribe { topic_id });
+        self.take_effects()
+    }
+
+    /// Unsubscribes from a topic, the lease at the rendezvous node runs out since it is not renewed anymore
+    pub fn unsubscribe(&mut self, topic: String) -> Vec<Effect> {
+        match self.subscribed_topics.remove(&chord::create_id(&topic)) {
+            Some(_) => self.storage.write_log_entry(format!("Unsubscribed from topic '{}'", topic)),
+            None => self.storage.write_log_entry(format!("Topic '{}' is not subscribed", topic)),
+        }
+        self.take_effects()
+    }
+
+    /// Renews the leases of all watched keys and subscribed topics. The renewal is routed like a new
+    /// watch or subscription, so it also reaches a new owner if the old one died without handing it over.
+    pub fn renew_leases(&mut self) -> Vec<Effect> {
+        if self.state.owns_keys() {
+            let me = self.to_other_node();
+            let key_ids: Vec<BigInt> = self.watched_keys.keys().cloned().collect();
+            for key_id in key_ids {
+                self.send_request(me.clone(), Request::DHTWatchKey { key_id });
+            }
+            let topic_ids: Vec<BigInt> = self.subscribed_topics.keys().cloned().collect();
+            for topic_id in topic_ids {
+                self.send_request(me.clone(), Request::Subscribe { topic_id });
+            }
+        }
+        self.take_effects()
+    }
+
+    /// Sends the keys of this node to the exporting thread, and starts walking the ring
+    /// from successor to successor if the keys of all nodes are requested
+    pub fn export_keys(&mut self, ring: bool, events: Sender<ExportEvent>) -> Vec<Effect> {
+        if self.pending_export.is_some() {
+            let _ = events.send(ExportEvent::Failed(ChordError::InvalidArgument("another export is still running".to_string())));
+            return self.take_effects();
+        }
+        if ring && !self.is_joined() {
+            let _ = events.send(ExportEvent::Failed(ChordError::NotJoined));
+            return self.take_effects();
+        }
+        let mut export = PendingExport::new(self.id.clone(), events);
+        let me = self.to_other_node();
+        let successor = if ring { self.get_successor() } else { me.clone() };
+        if let Some(next_node) = export.collect(me, self.storage.get_data_as_vec(), successor) {
+            self.pending_export = Some(export);
+            self.send_request(next_node, Request::DHTExportKeys);
+        }
+        self.take_effects()
+    }
+
+    /// Routes the entries through the ring as if they were stored one by one
+    pub fn import_keys(&mut self, data: Vec<(BigInt, DHTEntry)>, events: Sender<ImportEvent>) -> Vec<Effect> {
+        if self.pending_import.is_some() {
+            let _ = events.send(ImportEvent::Rejected(ChordError::InvalidArgument("another import is still running".to_string())));
+            return self.take_effects();
+        }
+        info!("[Node #{}] Importing {} entries", self.id, data.len());
+        self.pending_import = Some(PendingImport::new(data, events));
+        self.send_import_entries();
+        self.take_effects()
+    }
+
+    fn send_import_entries(&mut self) {
+        let next_operation_id = &mut self.next_operation_id;
+        let entries = match &mut self.pending_import {
+            Some(import) => import.next_entries(|| create_operation_id(next_operation_id)),
+            None => return,
+        };
+        let me = self.to_other_node();
+        for (operation, data) in entries {
+            self.send_operation_request(me.clone(), Request::DHTStoreKey { data }, Some(operation));
+        }
+    }
+
+    fn is_import_operation(&self, operation: Option<u64>) -> bool {
+        match (&self.pending_import, operation) {
+            (Some(import), Some(operation)) => import.is_in_flight(operation),
+            _ => false,
+        }
+    }
+
+    /// Passes the result of a store to the running import and sends the next entries.
+    /// Returns false if the operation does not belong to the import.
+    fn answer_import(&mut self, operation: u64, result: Result<OperationResult, ChordError>) -> bool {
+        let answered = match &mut self.pending_import {
+            Some(import) => import.answer(operation, result),
+            None => false,
+        };
+        if answered {
+            if self.pending_import.as_ref().is_some_and(|import| import.is_complete()) {
+                debug!("[Node #{}] Import finished", self.id);
+                self.pending_import = None;
+            } else {
+                self.send_import_entries();
+            }
+        }
+        answered
+    }
+
+    /// Sends a request of the user to this node itself, from where it is routed like the requests
+    /// of the node. Batches are registered first, so their result is complete only once every
+    /// key is answered. The reply, if any, gets the result once the last response arrived.
+    pub fn send_user_request(&mut self, request: Request, reply: Option<OperationReply>) -> Vec<Effect> {
+        match &request {
+            Request::DHTStoreKeys { batch_id, data } => {
+                let key_ids = data.iter().map(|(key_id, _)| key_id.clone()).collect();
+                self.pending_stores.insert(*batch_id, PendingBatch::new(key_ids));
+            }
+            Request::DHTFindKeys { batch_id, key_ids } => {
+                self.pending_finds.insert(*batch_id, PendingBatch::new(key_ids.clone()));
+            }
+            _ => {}
+        }
+        let operation = reply.map(|reply| {
+            let operation = create_operation_id(&mut self.next_operation_id);
+            self.operations.insert(operation, reply);
+            operation
+        });
+        let me = self.to_other_node();
+        self.send_operation_request(me, request, operation);
+        self.take_effects()
+    }
+
+    /// Sends the result to the frontend waiting for the operation, or to the running import
+    /// if the operation is one of its entries
+    fn answer_operation(&mut self, operation: Option<u64>, result: Result<OperationResult, ChordError>) {
+        let operation = match operation {
+            Some(operation) => operation,
+            None => return,
+        };
+        match self.operations.remove(&operation) {
+            Some(reply) => {
+                let _ = reply.send(result);
+            }
+            None => {
+                self.answer_import(operation, result);
+            }
+        }
+    }
+
+    /// Sends the current entry of a key to all nodes that watch it
+    fn notify_watchers(&mut self, key_id: &BigInt) {
+        let entry = self.storage.get_key(key_id);
+        for watcher in self.storage.get_watches().get_holders(key_id) {
+            debug!("[Node #{}] Notifying node #{} about key_id {}", self.id, watcher.id, key_id);
+            let req = Request::DHTKeyChanged { key_id: key_id.clone(), entry: entry.clone() };
+            let msg = Message::RequestMessage { sender: self.to_other_node(), request_id: 0, request: req };
+            self.send_message(watcher.ip_addr, msg);
         }
     }
 
-    fn check_redistribute_dht_keys(&mut self, pre_id: &BigInt) {
-        for (key, value) in self.storage.clone().get_data_as_iter() {
-            if !chord::is_my_key(&self.id, pre_id, key) {
-                let req = Request::DHTStoreKey { data: (key.clone(), value.clone()) };
-                network::send_request(self.to_other_node(), self.ip_addr, req);
-                self.storage.delete_key(key);
+    /// Sends a request that expects a response. The request is retried or rerouted
+    /// by `check_pending_requests` if the response does not arrive in time.
+    fn send_request(&mut self, target: OtherNode, request: Request) {
+        self.send_operation_request(target, request, None);
+    }
+
+    /// Sends a request whose response is, or leads to, the result of a user operation
+    fn send_operation_request(&mut self, target: OtherNode, request: Request, operation: Option<u64>) {
+        let pending = PendingRequest::new(target, request, self.clock.now()).for_operation(operation);
+        self.send_pending_request(pending);
+    }
+
+    fn send_pending_request(&mut self, pending: PendingRequest) {
+        let request_id = self.next_request_id;
+        self.next_request_id += 1;
+        let msg = Message::RequestMessage { sender: self.to_other_node(), request_id, request: pending.get_request().clone() };
+        self.send_message(pending.get_target().ip_addr, msg);
+        self.pending_requests.insert(request_id, pending);
+    }
+
+    /// Retries expired requests, reroutes them via the next best node once all retries
+    /// are used up and reports an error if no node is left to ask
+    pub fn check_pending_requests(&mut self) -> Vec<Effect> {
+        let now = self.clock.now();
+        let expired: Vec<u64> = self.pending_requests.iter()
+            .filter(|(_, pending)| pending.is_expired(now))
+            .map(|(request_id, _)| *request_id)
+            .collect();
+        for request_id in expired {
+            let mut pending = self.pending_requests.remove(&request_id).unwrap();
+            if pending.has_retries_left() {
+                debug!("[Node #{}] Request {} to node #{} timed out, retrying...", self.id, request_id, pending.get_target().id);
+                pending.retry(now);
+            } else if matches!(pending.get_request(), Request::DHTStoreKeys { .. } | Request::DHTFindKeys { .. }) {
+                debug!("[Node #{}] Node #{} did not answer batch request {}, rerouting its keys...",
+                       self.id, pending.get_target().id, request_id);
+                self.reroute_batch(pending);
+                continue;
+            } else {
+                let alternate = pending.get_request().get_routing_id().and_then(|routing_id| {
+                    let mut excluded = pending.get_failed_nodes().clone();
+                    excluded.push(pending.get_target().id.clone());
+                    self.closest_preceding_node_excluding(routing_id.clone(), &excluded)
+                });
+                match alternate {
+                    Some(next_node) => {
+                        debug!("[Node #{}] Node #{} did not answer request {}, asking node #{} instead...",
+                               self.id, pending.get_target().id, request_id, next_node.id);
+                        pending.reroute(next_node, now);
+                    }
+                    None => {
+                        self.report_failed_request(pending.get_request().clone(), pending.get_operation());
+                        continue;
+                    }
+                }
             }
+            let msg = Message::RequestMessage { sender: self.to_other_node(), request_id, request: pending.get_request().clone() };
+            self.send_message(pending.get_target().ip_addr, msg);
+            self.pending_requests.insert(request_id, pending);
         }
+        self.take_effects()
+    }
+
+    /// Sends the keys of a batch whose target did not answer to the next best node of each key,
+    /// since they may belong to different nodes. Keys without a node left to ask are reported as failed.
+    fn reroute_batch(&mut self, pending: PendingRequest) {
+        let mut excluded = pending.get_failed_nodes().clone();
+        excluded.push(pending.get_target().id.clone());
+        let (parts, failed) = match pending.get_request().clone() {
+            Request::DHTStoreKeys { batch_id, data } => {
+                let (groups, unreachable) = self.group_by_alternate_node(data, |(key_id, _)| key_id, &excluded);
+                let parts: Vec<(OtherNode, Request)> = groups.into_iter()
+                    .map(|(next_node, data)| (next_node, Request::DHTStoreKeys { batch_id, data }))
+                    .collect();
+                let failed = if unreachable.is_empty() { None } else { Some(Request::DHTStoreKeys { batch_id, data: unreachable }) };
+                (parts, failed)
+            }
+            Request::DHTFindKeys { batch_id, key_ids } => {
+                let (groups, unreachable) = self.group_by_alternate_node(key_ids, |key_id| key_id, &excluded);
+                let parts: Vec<(OtherNode, Request)> = groups.into_iter()
+                    .map(|(next_node, key_ids)| (next_node, Request::DHTFindKeys { batch_id, key_ids }))
+                    .collect();
+                let failed = if unreachable.is_empty() { None } else { Some(Request::DHTFindKeys { batch_id, key_ids: unreachable }) };
+                (parts, failed)
+            }
+            _ => return,
+        };
+        for (next_node, request) in parts {
+            let part = pending.split(next_node, request, self.clock.now());
+            self.send_pending_request(part);
+        }
+        if let Some(failed) = failed {
+            self.report_failed_request(failed, pending.get_operation());
+        }
+    }
+
+    fn report_failed_request(&mut self, request: Request, operation: Option<u64>) {
+        if let Request::DHTTakeOverKeys { data, .. } = &request {
+            if self.state == NodeState::Draining {
+                error!("[Node #{}] Successor did not take over the keys, {} keys are lost", self.id, data.len());
+                self.leave();
+                return;
+            }
+        }
+        if let Request::DHTStoreKeys { batch_id, .. } | Request::DHTFindKeys { batch_id, .. } = &request {
+            // The failed keys are reported with the result of their batch
+            error!("[Node #{}] Keys of batch {} failed, no reachable node left", self.id, batch_id);
+            self.fail_batch_request(&request, "no reachable node left", operation);
+            return;
+        }
+        let message = match request.get_routing_id() {
+            Some(routing_id) => format!("Lookup for id {} failed, no reachable node left", routing_id),
+            None => format!("Request {:?} failed, node is not reachable", request),
+        };
+        if let Request::DHTExportKeys = &request {
+            if let Some(export) = self.pending_export.take() {
+                export.fail(ChordError::Io(message.clone()));
+            }
+        }
+        self.storage.write_log_entry(message.clone());
+        error!("[Node #{}] {}", self.id, message);
+        self.answer_operation(operation, Err(ChordError::Unreachable(message)));
+    }
+
+    /// Records the keys of a batch request that failed as errors of their batch
+    fn fail_batch_request(&mut self, request: &Request, error: &str, operation: Option<u64>) {
+        match request {
+            Request::DHTStoreKeys { batch_id, data } => {
+                let failed = data.iter()
+                    .map(|(key_id, entry)| (key_id.clone(), format!("'{}' ({})", entry.get_key(), error)))
+                    .collect();
+                if self.pending_stores.get_mut(batch_id).is_some_and(|batch| batch.fail(failed)) {
+                    self.finish_store_batch(*batch_id, operation);
+                }
+            }
+            Request::DHTFindKeys { batch_id, key_ids } => {
+                let failed = key_ids.iter()
+                    .map(|key_id| (key_id.clone(), format!("{}=<failed: {}>", key_id, error)))
+                    .collect();
+                if self.pending_finds.get_mut(batch_id).is_some_and(|batch| batch.fail(failed)) {
+                    self.finish_find_batch(*batch_id, operation);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_responsible_for(&self, key_id: &BigInt) -> bool {
+        match &self.predecessor {
+            Some(predecessor) => chord::is_my_key(&self.id, predecessor.get_id(), key_id),
+            None => false
+        }
+    }
+
+    /// Groups items by the node they have to be forwarded to, so every group is routed only once
+    fn group_by_closest_preceding_node<T, F>(&self, items: Vec<T>, key_id: F) -> Vec<(OtherNode, Vec<T>)>
+        where F: Fn(&T) -> &BigInt {
+        let mut groups: HashMap<BigInt, (OtherNode, Vec<T>)> = HashMap::new();
+        for item in items {
+            let next_node = self.closest_preceding_node(key_id(&item).clone());
+            groups.entry(next_node.id.clone())
+                .or_insert_with(|| (next_node, Vec::new()))
+                .1.push(item);
+        }
+        groups.into_values().collect()
+    }
+
+    /// Groups items by the next best node for them that is not excluded, the items without
+    /// such a node are returned separately
+    fn group_by_alternate_node<T, F>(&self, items: Vec<T>, key_id: F, excluded: &[BigInt]) -> (Vec<(OtherNode, Vec<T>)>, Vec<T>)
+        where F: Fn(&T) -> &BigInt {
+        let mut groups: HashMap<BigInt, (OtherNode, Vec<T>)> = HashMap::new();
+        let mut unreachable = Vec::new();
+        for item in items {
+            match self.closest_preceding_node_excluding(key_id(&item).clone(), excluded) {
+                Some(next_node) => groups.entry(next_node.id.clone())
+                    .or_insert_with(|| (next_node, Vec::new()))
+                    .1.push(item),
+                None => unreachable.push(item),
+            }
+        }
+        (groups.into_values().collect(), unreachable)
+    }
+
+    /// Next best node for the given id that is not excluded, `None` if only this node is left
+    fn closest_preceding_node_excluding(&self, id: BigInt, excluded: &[BigInt]) -> Option<OtherNode> {
+        let candidates = (0..self.finger_table.length())
+            .map(|i| self.finger_table.get(i).get_node())
+            .chain(self.successor_list.iter())
+            .filter(|node| node.id != self.id && !excluded.contains(&node.id));
+        let mut best: Option<(BigInt, &OtherNode)> = None;
+        for node in candidates {
+            let finger_abs = chord::chord_abs(node.get_id(), &id);
+            if best.as_ref().is_none_or(|(min_abs, _)| &finger_abs < min_abs) {
+                best = Some((finger_abs, node));
+            }
+        }
+        best.map(|(_, node)| node.clone())
     }
 
     fn closest_preceding_node(&self, id: BigInt) -> OtherNode {
-        let mut min_abs: BigInt = BigInt::new(Sign::Plus, vec![u32::max_value(); 5]);
+        let mut min_abs: BigInt = BigInt::new(Sign::Plus, vec![u32::MAX; 5]);
         let mut return_node: OtherNode = self.to_other_node();
         for i in 0..self.finger_table.length() {
             let entry = self.finger_table.get(i);
@@ -171,60 +928,130 @@ impl Node {
         return_node
     }
 
-    pub fn process_incoming_request(&mut self, request: Request) -> Option<Response> {
-        match request {
+    fn process_incoming_request(&mut self, sender: &OtherNode, request: Request) -> Result<Option<Response>, ChordError> {
+        let allowed = match &request {
+            // Rejecting handed over keys would lose them, e.g. while this node is still joining
+            Request::DHTTakeOverKeys { .. } => true,
+            request if request.is_key_request() => self.state.owns_keys(),
+            _ => self.state.is_member(),
+        };
+        if !allowed {
+            return Err(match self.state {
+                NodeState::Joining => ChordError::NotJoined,
+                state => ChordError::Unavailable(state),
+            });
+        }
+        let response_option = match request {
             Request::FindSuccessor { id } => {
-                debug!("[Node #{}] Request::FindSuccessor(id: {})", self.clone().id, id.clone());
-                Some(self.handle_find_successor_request(id))
+                debug!("[Node #{}] Request::FindSuccessor(id: {})", self.id, id.clone());
+                Some(self.handle_find_successor_request(sender, id)?)
             }
             Request::GetPredecessor => {
-                debug!("[Node #{}] Request::GetPredecessor", self.clone().id);
+                debug!("[Node #{}] Request::GetPredecessor", self.id);
                 Some(self.handle_get_predecessor_request())
             }
             Request::Notify { node } => {
-                debug!("[Node #{}] Request::Notify(node: {})", self.clone().id, node.id.clone());
+                debug!("[Node #{}] Request::Notify(node: {})", self.id, node.id.clone());
                 Some(self.handle_notify_request(node))
             }
             Request::FindSuccessorFinger { index, finger_id } => {
-                debug!("[Node #{}] Request::FindSuccessorFinger(index: {} finger_id: {})", self.clone().id, index, finger_id.clone());
+                debug!("[Node #{}] Request::FindSuccessorFinger(index: {} finger_id: {})", self.id, index, finger_id.clone());
                 Some(self.handle_find_successor_finger_request(index, finger_id))
             }
             Request::GetSuccessorList => {
-                debug!("[Node #{}] Request::GetSuccessorList", self.clone().id);
+                debug!("[Node #{}] Request::GetSuccessorList", self.id);
                 Some(self.handle_get_successor_list_request())
             }
             Request::DHTStoreKey { data } => {
-                info!("[Node #{}] Request::StoreKey(data: {:?})", self.clone().id, data.clone());
+                info!("[Node #{}] Request::StoreKey(data: {:?})", self.id, data.clone());
                 Some(self.handle_dht_store_key_request(data))
             }
+            Request::DHTStoreKeyIf { data, condition } => {
+                info!("[Node #{}] Request::StoreKeyIf(data: {:?}, condition: {:?})", self.id, data.clone(), condition);
+                Some(self.handle_dht_store_key_if_request(data, condition))
+            }
+            Request::DHTStoreKeys { batch_id, data } => {
+                info!("[Node #{}] Request::StoreKeys(batch_id: {}, data: {:?})", self.id, batch_id, data.clone());
+                Some(self.handle_dht_store_keys_request(batch_id, data))
+            }
+            Request::DHTFindKeys { batch_id, key_ids } => {
+                info!("[Node #{}] Request::FindKeys(batch_id: {}, key_ids: {:?})", self.id, batch_id, key_ids.clone());
+                Some(self.handle_dht_find_keys_request(batch_id, key_ids))
+            }
             Request::DHTFindKey { key_id } => {
-                info!("[Node #{}] Request::FindKey(key_id: {})", self.clone().id, key_id.clone());
+                info!("[Node #{}] Request::FindKey(key_id: {})", self.id, key_id.clone());
                 Some(self.handle_dht_find_key_request(key_id))
             }
             Request::DHTDeleteKey { key_id } => {
-                info!("[Node #{}] Request::DeleteKey(key_id {})", self.clone().id, key_id.clone());
+                info!("[Node #{}] Request::DeleteKey(key_id {})", self.id, key_id.clone());
                 Some(self.handle_dht_delete_key_request(key_id))
             }
-            Request::DHTTakeOverKeys { data } => {
-                info!("[Node #{}] Request::DHTTakeOverKey(data {:?})", self.clone().id, data.clone());
-                self.handle_dht_take_over_keys(data);
+            Request::DHTFindOwner { key_id } => {
+                info!("[Node #{}] Request::FindOwner(key_id {})", self.id, key_id.clone());
+                Some(self.handle_dht_find_owner_request(key_id))
+            }
+            Request::DHTWatchKey { key_id } => {
+                info!("[Node #{}] Request::WatchKey(key_id {})", self.id, key_id.clone());
+                Some(self.handle_dht_watch_key_request(sender, key_id))
+            }
+            Request::DHTKeyChanged { key_id, entry } => {
+                info!("[Node #{}] Request::KeyChanged(key_id {}, entry {:?})", self.id, key_id.clone(), entry);
+                self.handle_dht_key_changed_request(key_id, entry);
                 None
             }
-        }
+            Request::Subscribe { topic_id } => {
+                info!("[Node #{}] Request::Subscribe(topic_id {})", self.id, topic_id.clone());
+                Some(self.handle_subscribe_request(sender, topic_id))
+            }
+            Request::Publish { topic_id, topic, message } => {
+                info!("[Node #{}] Request::Publish(topic_id {}, topic {}, message {})", self.id, topic_id.clone(), topic, message);
+                Some(self.handle_publish_request(topic_id, topic, message))
+            }
+            Request::Deliver { topic_id, topic, message } => {
+                info!("[Node #{}] Request::Deliver(topic_id {}, topic {}, message {})", self.id, topic_id.clone(), topic, message);
+                self.handle_deliver_request(topic_id, topic, message);
+                None
+            }
+            Request::DHTTakeOverKeys { data, tombstones, watches, subscriptions } => {
+                info!("[Node #{}] Request::DHTTakeOverKey(data {:?}, tombstones {:?}, watches {:?}, subscriptions {:?})",
+                      self.id, data.clone(), tombstones, watches, subscriptions);
+                Some(self.handle_dht_take_over_keys(data, tombstones, watches, subscriptions))
+            }
+            Request::DHTExportKeys => {
+                info!("[Node #{}] Request::ExportKeys", self.id);
+                Some(self.handle_dht_export_keys_request())
+            }
+        };
+        Ok(response_option)
     }
 
-    pub fn process_incoming_response(&mut self, response: Response) {
+    fn process_incoming_response(&mut self, sender: OtherNode, request_id: u64, response: Response) {
+        // The request is needed to tell what an error response refers to
+        let (request, operation) = if request_id != 0 {
+            match self.pending_requests.remove(&request_id) {
+                Some(pending) => {
+                    let operation = pending.get_operation();
+                    (Some(pending.into_request()), operation)
+                }
+                None => {
+                    debug!("[Node #{}] Ignoring response to request {}, it was already answered or given up", self.id, request_id);
+                    return;
+                }
+            }
+        } else {
+            (None, None)
+        };
         match response {
             Response::FoundSuccessor { successor } => {
-                debug!("[Node #{}] Response::FoundSuccessor(successor: {})", self.clone().id, successor.id.clone());
+                debug!("[Node #{}] Response::FoundSuccessor(successor: {})", self.id, successor.id.clone());
                 self.handle_found_successor_response(successor)
             }
             Response::AskFurther { next_node } => {
-                debug!("[Node #{}] Response::AskFurther(next_node: {}", self.clone().id, next_node.id.clone());
-                self.handle_ask_further_response(next_node)
+                debug!("[Node #{}] Response::AskFurther(next_node: {}", self.id, next_node.id.clone());
+                self.handle_ask_further_response(sender, next_node)
             }
             Response::GetPredecessorResponse { predecessor } => {
-                debug!("[Node #{}] Response::GetPredecessorResponse(predecessor: {:?})", self.clone().id, predecessor.clone());
+                debug!("[Node #{}] Response::GetPredecessorResponse(predecessor: {:?})", self.id, predecessor.clone());
                 self.handle_get_predecessor_response(predecessor)
             }
             Response::NotifyResponse => {
@@ -233,52 +1060,130 @@ impl Node {
             }
             Response::FoundSuccessorFinger { index, finger_id, successor } => {
                 debug!("[Node #{}] Response::FoundSuccessorFinger(index: {}, finger_id: {}, successor: {})",
-                       self.clone().id, index, finger_id.clone(), successor.id.clone());
+                       self.id, index, finger_id.clone(), successor.id.clone());
                 self.handle_found_successor_finger_response(index, finger_id, successor)
             }
             Response::AskFurtherFinger { index, finger_id, next_node } => {
-                debug!("[Node #{}] Response::AskFurtherFinger(next_node: {}", self.clone().id, next_node.id.clone());
+                debug!("[Node #{}] Response::AskFurtherFinger(next_node: {}", self.id, next_node.id.clone());
                 self.handle_ask_further_finger_response(index, finger_id, next_node)
             }
             Response::GetSuccessorListResponse { successor_list } => {
                 debug!("[Node #{}] Response::GetSuccessorListResponse(successor_list: {:?}",
-                       self.clone().id, successor_list.clone());
+                       self.id, successor_list.clone());
                 self.handle_get_successor_list_response(successor_list)
             }
-            Response::DHTStoredKey{ key} => {
-                debug!("[Node #{}] Response::DHTStoredKey", self.clone().id);
-                self.handle_dht_stored_key_response(key)
+            Response::DHTStoredKey { key, version, applied } => {
+                debug!("[Node #{}] Response::DHTStoredKey(key: {}, version: {}, applied: {})", self.id, key, version, applied);
+                self.handle_dht_stored_key_response(key, version, applied, operation)
+            }
+            Response::DHTConditionalStoredKey { key, applied, version, current } => {
+                debug!("[Node #{}] Response::DHTConditionalStoredKey(key: {}, applied: {}, version: {}, current: {:?})",
+                       self.id, key, applied, version, current);
+                self.handle_dht_conditional_stored_key_response(key, applied, version, current, operation)
+            }
+            Response::DHTStoredKeys { batch_id, stored, ask_further } => {
+                debug!("[Node #{}] Response::DHTStoredKeys(batch_id: {}, stored: {:?}, ask_further: {:?})",
+                       self.id, batch_id, stored, ask_further);
+                self.handle_dht_stored_keys_response(batch_id, stored, ask_further, operation)
+            }
+            Response::DHTFoundKeys { batch_id, data, ask_further } => {
+                debug!("[Node #{}] Response::DHTFoundKeys(batch_id: {}, data: {:?}, ask_further: {:?})",
+                       self.id, batch_id, data, ask_further);
+                self.handle_dht_found_keys_response(batch_id, data, ask_further, operation)
             }
             Response::DHTFoundKey { data } => {
-                debug!("[Node #{}] Response::DHTFoundKey(data: {:?})", self.clone().id, data.clone());
-                self.handle_dht_found_key_response(data)
+                debug!("[Node #{}] Response::DHTFoundKey(data: {:?})", self.id, data.clone());
+                self.handle_dht_found_key_response(data, operation)
             }
             Response::DHTDeletedKey { key_existed } => {
-                debug!("[Node #{}] Response::DHTDeletedKey(key_existed: {})", self.clone().id, key_existed);
-                self.handle_dht_deleted_key_response(key_existed)
+                debug!("[Node #{}] Response::DHTDeletedKey(key_existed: {})", self.id, key_existed);
+                self.handle_dht_deleted_key_response(key_existed, operation)
+            }
+            Response::DHTFoundOwner { key_id, owner } => {
+                debug!("[Node #{}] Response::DHTFoundOwner(key_id: {}, owner: {})", self.id, key_id, owner.id.clone());
+                self.handle_dht_found_owner_response(key_id, owner, operation)
+            }
+            Response::DHTTookOverKeys => {
+                debug!("[Node #{}] Response::DHTTookOverKeys", self.id);
+                self.handle_dht_took_over_keys_response(request)
+            }
+            Response::DHTExportedKeys { data, successor } => {
+                debug!("[Node #{}] Response::DHTExportedKeys(data: {:?}, successor: {})", self.id, data, successor.id.clone());
+                self.handle_dht_exported_keys_response(sender, data, successor)
             }
             Response::DHTAskFurtherStore { next_node, data } => {
                 info!("[Node #{}] Response::DHTAskFurtherStore(next_node: {}, data: {:?})",
-                      self.clone().id, next_node.get_id().clone(), data);
-                self.handle_dht_ask_further_store_response(next_node, data)
+                      self.id, next_node.get_id().clone(), data);
+                self.handle_dht_ask_further_store_response(next_node, data, operation)
+            }
+            Response::DHTAskFurtherStoreIf { next_node, data, condition } => {
+                info!("[Node #{}] Response::DHTAskFurtherStoreIf(next_node: {}, data: {:?}, condition: {:?})",
+                      self.id, next_node.get_id().clone(), data, condition);
+                self.handle_dht_ask_further_store_if_response(next_node, data, condition, operation)
             }
             Response::DHTAskFurtherFind { next_node, key_id } => {
                 info!("[Node #{}] Response::DHTAskFurtherFind(next_node: {}, key_id: {})",
-                      self.clone().id, next_node.get_id().clone(), key_id.clone());
-                self.handle_dht_ask_further_find_response(next_node, key_id)
+                      self.id, next_node.get_id().clone(), key_id.clone());
+                self.handle_dht_ask_further_find_response(next_node, key_id, operation)
             }
             Response::DHTAskFurtherDelete { next_node, key_id } => {
                 info!("[Node #{}] Response::DHTAskFurtherDelete(next_node: {}, key_id: {})",
-                      self.clone().id, next_node.get_id().clone(), key_id.clone());
-                self.handle_dht_ask_further_delete_response(next_node, key_id)
+                      self.id, next_node.get_id().clone(), key_id.clone());
+                self.handle_dht_ask_further_delete_response(next_node, key_id, operation)
+            }
+            Response::DHTAskFurtherFindOwner { next_node, key_id } => {
+                info!("[Node #{}] Response::DHTAskFurtherFindOwner(next_node: {}, key_id: {})",
+                      self.id, next_node.get_id().clone(), key_id.clone());
+                self.handle_dht_ask_further_find_owner_response(next_node, key_id, operation)
+            }
+            Response::DHTWatchedKey { key_id, current } => {
+                debug!("[Node #{}] Response::DHTWatchedKey(key_id: {}, current: {:?})", self.id, key_id, current);
+                self.handle_dht_watched_key_response(key_id, current)
+            }
+            Response::DHTAskFurtherWatch { next_node, key_id } => {
+                info!("[Node #{}] Response::DHTAskFurtherWatch(next_node: {}, key_id: {})",
+                      self.id, next_node.get_id().clone(), key_id.clone());
+                self.handle_dht_ask_further_watch_response(next_node, key_id)
+            }
+            Response::Subscribed { topic_id } => {
+                debug!("[Node #{}] Response::Subscribed(topic_id: {})", self.id, topic_id);
+                self.handle_subscribed_response(topic_id)
+            }
+            Response::Published { topic, subscribers } => {
+                debug!("[Node #{}] Response::Published(topic: {}, subscribers: {})", self.id, topic, subscribers);
+                self.handle_published_response(topic, subscribers)
+            }
+            Response::AskFurtherSubscribe { next_node, topic_id } => {
+                info!("[Node #{}] Response::AskFurtherSubscribe(next_node: {}, topic_id: {})",
+                      self.id, next_node.get_id().clone(), topic_id.clone());
+                self.handle_ask_further_subscribe_response(next_node, topic_id)
+            }
+            Response::AskFurtherPublish { next_node, topic_id, topic, message } => {
+                info!("[Node #{}] Response::AskFurtherPublish(next_node: {}, topic_id: {}, topic: {})",
+                      self.id, next_node.get_id().clone(), topic_id.clone(), topic);
+                self.handle_ask_further_publish_response(next_node, topic_id, topic, message)
+            }
+            Response::Error { error } => {
+                warn!("[Node #{}] Response::Error(error: {})", self.id, error);
+                self.handle_error_response(error, request, operation)
             }
         }
     }
 
     // REQUESTS
 
-    fn handle_find_successor_request(&self, id: BigInt) -> Response {
-        if chord::is_in_interval(&self.id, self.get_successor().get_id(), &id) {
+    fn handle_find_successor_request(&self, sender: &OtherNode, id: BigInt) -> Result<Response, ChordError> {
+        // A node joining with the id of another node would corrupt the ring.
+        // The lookup for an existing id always ends at the node itself or at its predecessor.
+        let existing_option = iter::once(self.to_other_node())
+            .chain(iter::once(self.get_successor()))
+            .find(|node| node.id == id);
+        if let Some(existing) = existing_option {
+            if existing.ip_addr != sender.ip_addr {
+                return Err(ChordError::IdCollision(id, existing.ip_addr));
+            }
+        }
+        let response = if chord::is_in_interval(&self.id, self.get_successor().get_id(), &id) {
             Response::FoundSuccessor { successor: self.get_successor().clone() }
         } else if let Some(pre) = self.predecessor.clone() {
             if chord::is_in_interval(pre.get_id(), &self.id, &id) {
@@ -288,7 +1193,8 @@ impl Node {
             }
         } else {
             Response::AskFurther { next_node: self.closest_preceding_node(id) }
-        }
+        };
+        Ok(response)
     }
 
     fn handle_get_predecessor_request(&self) -> Response {
@@ -298,6 +1204,14 @@ impl Node {
     }
 
     fn handle_notify_request(&mut self, node: OtherNode) -> Response {
+        // A node that is alone in the ring takes the notifying node as successor and notifies it
+        // in turn, before moving keys to it. Otherwise both would route the keys back and forth.
+        if self.get_successor().id == self.id && node.id != self.id {
+            debug!("[Node #{}] Notify: Had no other node, successor is now: {}", self.id, node.id);
+            self.update_successor_and_successor_list(node.clone());
+            let req = Request::Notify { node: self.to_other_node() };
+            self.send_request(node.clone(), req);
+        }
         match &self.predecessor {
             None => {
                 debug!("[Node #{}] Notify: Had no Pre. Pre is now: {}", self.id, node.id);
@@ -332,9 +1246,17 @@ impl Node {
         if let Some(predecessor) = self.predecessor.clone() {
             // I am responsible for the key
             if chord::is_my_key(&self.id, predecessor.get_id(), &data.0) {
-                self.storage.store_key(data.clone());
-                Response::DHTStoredKey{
-                    key: data.1.get_key().to_owned()
+                let (version, applied) = match self.storage.store_key(data.clone()) {
+                    Ok(version) => (version, true),
+                    Err(version) => (version, false),
+                };
+                if applied {
+                    self.notify_watchers(&data.0);
+                }
+                Response::DHTStoredKey {
+                    key: data.1.get_key().to_owned(),
+                    version,
+                    applied,
                 }
             } else {
                 Response::DHTAskFurtherStore {
@@ -350,12 +1272,81 @@ impl Node {
         }
     }
 
+    fn handle_dht_store_key_if_request(&mut self,
+                                       data: (BigInt, DHTEntry),
+                                       condition: WriteCondition) -> Response {
+        if let Some(predecessor) = self.predecessor.clone() {
+            // I am responsible for the key
+            if chord::is_my_key(&self.id, predecessor.get_id(), &data.0) {
+                let key = data.1.get_key().to_owned();
+                let key_id = data.0.clone();
+                match self.storage.store_key_if(data, &condition) {
+                    Ok(version) => {
+                        self.notify_watchers(&key_id);
+                        Response::DHTConditionalStoredKey { key, applied: true, version, current: None }
+                    }
+                    Err(current) => Response::DHTConditionalStoredKey {
+                        key,
+                        applied: false,
+                        version: current.as_ref().map_or(0, |entry| entry.get_version()),
+                        current,
+                    },
+                }
+            } else {
+                Response::DHTAskFurtherStoreIf {
+                    next_node: self.closest_preceding_node(data.0.clone()),
+                    data,
+                    condition,
+                }
+            }
+        } else {
+            Response::DHTAskFurtherStoreIf {
+                next_node: self.closest_preceding_node(data.0.clone()),
+                data,
+                condition,
+            }
+        }
+    }
+
+    fn handle_dht_store_keys_request(&mut self, batch_id: u64, data: Vec<(BigInt, DHTEntry)>) -> Response {
+        let (mine, others): (Vec<_>, Vec<_>) = data.into_iter()
+            .partition(|(key_id, _)| self.is_responsible_for(key_id));
+        let stored = mine.into_iter()
+            .map(|(key_id, entry)| {
+                let key = entry.get_key().to_owned();
+                let version = match self.storage.store_key((key_id.clone(), entry)) {
+                    Ok(version) => {
+                        self.notify_watchers(&key_id);
+                        version
+                    }
+                    Err(version) => version,
+                };
+                (key_id, key, version)
+            })
+            .collect();
+        let ask_further = self.group_by_closest_preceding_node(others, |(key_id, _)| key_id);
+        Response::DHTStoredKeys { batch_id, stored, ask_further }
+    }
+
+    fn handle_dht_find_keys_request(&self, batch_id: u64, key_ids: Vec<BigInt>) -> Response {
+        let (mine, others): (Vec<_>, Vec<_>) = key_ids.into_iter()
+            .partition(|key_id| self.is_responsible_for(key_id));
+        let data = mine.into_iter()
+            .map(|key_id| {
+                let value_option = self.storage.get_key(&key_id);
+                (key_id, value_option)
+            })
+            .collect();
+        let ask_further = self.group_by_closest_preceding_node(others, |key_id| key_id);
+        Response::DHTFoundKeys { batch_id, data, ask_further }
+    }
+
     fn handle_dht_find_key_request(&self, key_id: BigInt) -> Response {
         if let Some(predecessor) = self.predecessor.clone() {
             // I am responsible for the key
             if chord::is_my_key(&self.id, predecessor.get_id(), &key_id) {
                 let value_option = self.storage.get_key(&key_id);
-                Response::DHTFoundKey { data: (key_id, value_option.cloned()) }
+                Response::DHTFoundKey { data: (key_id, value_option) }
             } else {
                 Response::DHTAskFurtherFind {
                     next_node: self.closest_preceding_node(key_id.clone()),
@@ -375,6 +1366,9 @@ impl Node {
             // I am responsible for the key
             if chord::is_my_key(&self.id, predecessor.get_id(), &key_id) {
                 let key_existed = self.storage.delete_key(&key_id).is_some();
+                if key_existed {
+                    self.notify_watchers(&key_id);
+                }
                 Response::DHTDeletedKey { key_existed }
             } else {
                 Response::DHTAskFurtherDelete {
@@ -390,10 +1384,96 @@ impl Node {
         }
     }
 
-    fn handle_dht_take_over_keys(&mut self, data: Vec<(BigInt, DHTEntry)>) {
+    fn handle_dht_find_owner_request(&self, key_id: BigInt) -> Response {
+        if self.is_responsible_for(&key_id) {
+            Response::DHTFoundOwner { key_id, owner: self.to_other_node() }
+        } else {
+            Response::DHTAskFurtherFindOwner {
+                next_node: self.closest_preceding_node(key_id.clone()),
+                key_id,
+            }
+        }
+    }
+
+    fn handle_dht_watch_key_request(&mut self, sender: &OtherNode, key_id: BigInt) -> Response {
+        if self.is_responsible_for(&key_id) {
+            self.storage.get_watches_mut().add(key_id.clone(), Lease::new(sender.clone()));
+            let current = self.storage.get_key(&key_id);
+            Response::DHTWatchedKey { key_id, current }
+        } else {
+            Response::DHTAskFurtherWatch {
+                next_node: self.closest_preceding_node(key_id.clone()),
+                key_id,
+            }
+        }
+    }
+
+    fn handle_dht_key_changed_request(&mut self, key_id: BigInt, entry: Option<DHTEntry>) {
+        self.update_watched_key(key_id, entry);
+    }
+
+    fn handle_subscribe_request(&mut self, sender: &OtherNode, topic_id: BigInt) -> Response {
+        if self.is_responsible_for(&topic_id) {
+            self.storage.get_subscriptions_mut().add(topic_id.clone(), Lease::new(sender.clone()));
+            Response::Subscribed { topic_id }
+        } else {
+            Response::AskFurtherSubscribe {
+                next_node: self.closest_preceding_node(topic_id.clone()),
+                topic_id,
+            }
+        }
+    }
+
+    /// Fans the message out to all subscribers of the topic, if this is its rendezvous node
+    fn handle_publish_request(&mut self, topic_id: BigInt, topic: String, message: String) -> Response {
+        if self.is_responsible_for(&topic_id) {
+            let subscribers = self.storage.get_subscriptions().get_holders(&topic_id);
+            for subscriber in &subscribers {
+                let req = Request::Deliver { topic_id: topic_id.clone(), topic: topic.clone(), message: message.clone() };
+                let msg = Message::RequestMessage { sender: self.to_other_node(), request_id: 0, request: req };
+                self.send_message(subscriber.ip_addr, msg);
+            }
+            Response::Published { topic, subscribers: subscribers.len() }
+        } else {
+            Response::AskFurtherPublish {
+                next_node: self.closest_preceding_node(topic_id.clone()),
+                topic_id,
+                topic,
+                message,
+            }
+        }
+    }
+
+    fn handle_deliver_request(&mut self, topic_id: BigInt, topic: String, message: String) {
+        // Messages of topics that were unsubscribed arrive until the lease runs out
+        if self.subscribed_topics.contains_key(&topic_id) {
+            self.storage.write_log_entry(format!("Topic '{}': {}", topic, message));
+        }
+    }
+
+    fn handle_dht_export_keys_request(&self) -> Response {
+        Response::DHTExportedKeys { data: self.storage.get_data_as_vec(), successor: self.get_successor() }
+    }
+
+    fn handle_dht_take_over_keys(&mut self,
+                                 data: Vec<(BigInt, DHTEntry)>,
+                                 tombstones: Vec<(BigInt, Tombstone)>,
+                                 watches: Vec<(BigInt, Lease)>,
+                                 subscriptions: Vec<(BigInt, Lease)>) -> Response {
+        // Tombstones first, so entries that were deleted since are dropped when they are merged
+        for (key_id, tombstone) in tombstones {
+            self.storage.merge_tombstone(key_id, tombstone);
+        }
         for entry in data {
-            self.storage.store_key(entry);
+            self.storage.merge_key(entry);
         }
+        for (key_id, lease) in watches {
+            self.storage.get_watches_mut().add(key_id, lease);
+        }
+        for (topic_id, lease) in subscriptions {
+            self.storage.get_subscriptions_mut().add(topic_id, lease);
+        }
+        Response::DHTTookOverKeys
     }
 
 
@@ -401,16 +1481,30 @@ impl Node {
 
     fn handle_found_successor_response(&mut self, successor: OtherNode) {
         debug!("Found my new successor: node #{}", successor.id.clone());
+        if self.state == NodeState::Joining {
+            // Replaces the dead successors of a former membership
+            self.successor_list = vec![successor.clone()];
+            self.rejoin_candidates = None;
+        }
         self.update_successor_and_successor_list(successor);
-        if !self.joined {
-            self.joined = true;
+        if self.state == NodeState::Joining {
+            if let Err(e) = self.transition(NodeState::Active) {
+                warn!("[Node #{}] {}", self.id, e);
+            }
         }
     }
 
-    fn handle_ask_further_response(&mut self, next_node: OtherNode) {
+    fn handle_ask_further_response(&mut self, sender: OtherNode, next_node: OtherNode) {
+        if next_node.id == self.id {
+            // While rejoining, the asked node may still know this node as its successor.
+            // It precedes this node then, stabilization moves the successor forward from there.
+            debug!("Node #{} still points to me, taking it as successor", sender.id);
+            self.handle_found_successor_response(sender);
+            return;
+        }
         debug!("Did not get successor yet, asking node #{} now...", next_node.id);
         let req = Request::FindSuccessor { id: self.id.clone() };
-        network::send_request(self.to_other_node(),next_node.ip_addr, req);
+        self.send_request(next_node, req);
     }
 
     fn handle_get_predecessor_response(&mut self, predecessor: Option<OtherNode>) {
@@ -423,7 +1517,8 @@ impl Node {
             }
         }
         let req = Request::Notify { node: self.to_other_node() };
-        network::send_request(self.to_other_node(), self.get_successor().ip_addr, req);
+        let successor = self.get_successor();
+        self.send_request(successor, req);
     }
 
     fn handle_notify_response(&self) {}
@@ -441,7 +1536,7 @@ impl Node {
         debug!("Did not get entry for finger {} (#{}) yet, asking node #{} now...", finger_id.clone(), index, next_node.id);
         let req = Request::FindSuccessorFinger { index, finger_id };
 
-        network::send_request(self.to_other_node(),next_node.ip_addr, req);
+        self.send_request(next_node, req);
     }
 
     fn handle_get_successor_list_response(&mut self, successor_list: Vec<OtherNode>) {
@@ -454,22 +1549,144 @@ impl Node {
         self.successor_list = new_successor_list;
     }
 
-    fn handle_dht_stored_key_response(&mut self, key: String) {
-        self.storage.write_log_entry(format!("Key '{}' stored", key));
-        debug!("Key '{}' stored", key);
+    fn handle_dht_stored_key_response(&mut self, key: String, version: u64, applied: bool, operation: Option<u64>) {
+        // Imported keys are reported to the importing thread instead of the storage log
+        if !self.is_import_operation(operation) {
+            if applied {
+                self.storage.write_log_entry(format!("Key '{}' stored (version {})", key, version));
+            } else {
+                self.storage.write_log_entry(format!("Key '{}' not stored, newer version {} exists", key, version));
+            }
+        }
+        let stored = StoredKey { key, version };
+        if applied {
+            debug!("Key '{}' stored (version {})", stored.key, version);
+            self.answer_operation(operation, Ok(OperationResult::Stored(stored)));
+        } else {
+            debug!("Key '{}' not stored, newer version {} exists", stored.key, version);
+            self.answer_operation(operation, Ok(OperationResult::Kept(stored)));
+        }
     }
 
-    fn handle_dht_found_key_response(&mut self, data: (BigInt, Option<DHTEntry>)) {
+    fn handle_dht_conditional_stored_key_response(&mut self,
+                                                  key: String,
+                                                  applied: bool,
+                                                  version: u64,
+                                                  current: Option<DHTEntry>,
+                                                  operation: Option<u64>) {
+        if applied {
+            self.storage.write_log_entry(format!("Key '{}' stored (version {})", key, version));
+            debug!("Key '{}' stored (version {})", key, version);
+        } else if let Some(current) = &current {
+            self.storage.write_log_entry(format!("Key '{}' not stored, current value is {} (version {})", key, current.get_value(), version));
+            debug!("Key '{}' not stored, current value is '{}' (version {})", key, current.get_value(), version);
+        } else {
+            self.storage.write_log_entry(format!("Key '{}' not stored, key is not present in the network", key));
+            debug!("Key '{}' not stored, key is not present in the network", key);
+        }
+        let current_value = if applied { None } else { current.map(|current| current.get_value().clone()) };
+        self.answer_operation(operation, Ok(OperationResult::ConditionalStored { key, applied, version, current_value }));
+    }
+
+    fn handle_dht_found_key_response(&mut self, data: (BigInt, Option<DHTEntry>), operation: Option<u64>) {
         if let Some(dht_entry) = data.1.clone() {
-            self.storage.write_log_entry(format!("Value for key {} (id: {}) is {}", dht_entry.get_key(), data.0, dht_entry.get_value()));
-            debug!("Value for key '{}' (id: {}) is '{}'", dht_entry.get_key(), data.0, dht_entry.get_value());
+            self.storage.write_log_entry(format!("Value for key {} (id: {}) is {} (version {})", dht_entry.get_key(), data.0, dht_entry.get_value(), dht_entry.get_version()));
+            debug!("Value for key '{}' (id: {}) is '{}' (version {})", dht_entry.get_key(), data.0, dht_entry.get_value(), dht_entry.get_version());
         } else {
             self.storage.write_log_entry(format!("No value for key_id {} found in the network", data.0));
             debug!("No value for key_id '{}' found in the network", data.0)
         }
+        let (key_id, entry) = data;
+        self.answer_operation(operation, Ok(OperationResult::Found(FoundKey::new(key_id, entry.as_ref()))));
     }
 
-    fn handle_dht_deleted_key_response(&mut self, key_existed: bool) {
+    fn handle_dht_stored_keys_response(&mut self,
+                                       batch_id: u64,
+                                       stored: Vec<(BigInt, String, u64)>,
+                                       ask_further: Vec<(OtherNode, Vec<(BigInt, DHTEntry)>)>,
+                                       operation: Option<u64>) {
+        let answered = stored.into_iter().map(|(key_id, key, version)| (key_id, (key, version))).collect();
+        let complete = match self.pending_stores.get_mut(&batch_id) {
+            Some(batch) => batch.add(answered),
+            None => {
+                debug!("[Node #{}] Ignoring response for batch {}, it is unknown or already complete", self.id, batch_id);
+                return;
+            }
+        };
+
+        for (next_node, data) in ask_further {
+            debug!("Did not store {} keys of batch {} yet, asking node #{} now...", data.len(), batch_id, next_node.id);
+            let req = Request::DHTStoreKeys { batch_id, data };
+            self.send_operation_request(next_node, req, operation);
+        }
+
+        if complete {
+            self.finish_store_batch(batch_id, operation);
+        }
+    }
+
+    fn finish_store_batch(&mut self, batch_id: u64, operation: Option<u64>) {
+        let (results, errors) = match self.pending_stores.remove(&batch_id) {
+            Some(batch) => batch.into_results(),
+            None => return,
+        };
+        let keys: Vec<String> = results.iter()
+            .map(|(key, version)| format!("'{}' (version {})", key, version))
+            .collect();
+        let mut message = format!("Batch {}: stored {} keys: {}", batch_id, results.len(), keys.join(", "));
+        if !errors.is_empty() {
+            message.push_str(&format!("; {} failed: {}", errors.len(), errors.join(", ")));
+        }
+        self.storage.write_log_entry(message.clone());
+        debug!("{}", message);
+        let stored = results.into_iter().map(|(key, version)| StoredKey { key, version }).collect();
+        self.answer_operation(operation, Ok(OperationResult::BatchStored { stored, failed: errors }));
+    }
+
+    fn handle_dht_found_keys_response(&mut self,
+                                      batch_id: u64,
+                                      data: Vec<(BigInt, Option<DHTEntry>)>,
+                                      ask_further: Vec<(OtherNode, Vec<BigInt>)>,
+                                      operation: Option<u64>) {
+        let answered = data.into_iter().map(|result| (result.0.clone(), result)).collect();
+        let complete = match self.pending_finds.get_mut(&batch_id) {
+            Some(batch) => batch.add(answered),
+            None => {
+                debug!("[Node #{}] Ignoring response for batch {}, it is unknown or already complete", self.id, batch_id);
+                return;
+            }
+        };
+
+        for (next_node, key_ids) in ask_further {
+            debug!("Did not find {} keys of batch {} yet, asking node #{} now...", key_ids.len(), batch_id, next_node.id);
+            let req = Request::DHTFindKeys { batch_id, key_ids };
+            self.send_operation_request(next_node, req, operation);
+        }
+
+        if complete {
+            self.finish_find_batch(batch_id, operation);
+        }
+    }
+
+    fn finish_find_batch(&mut self, batch_id: u64, operation: Option<u64>) {
+        let (results, errors) = match self.pending_finds.remove(&batch_id) {
+            Some(batch) => batch.into_results(),
+            None => return,
+        };
+        let values: Vec<String> = results.iter()
+            .map(|(key_id, value_option)| match value_option {
+                Some(dht_entry) => format!("{}={} (version {})", dht_entry.get_key(), dht_entry.get_value(), dht_entry.get_version()),
+                None => format!("{}=<not found>", key_id),
+            })
+            .chain(errors.iter().cloned())
+            .collect();
+        self.storage.write_log_entry(format!("Batch {}: {}", batch_id, values.join(", ")));
+        debug!("Batch {}: {}", batch_id, values.join(", "));
+        let found = results.into_iter().map(|(key_id, entry)| FoundKey::new(key_id, entry.as_ref())).collect();
+        self.answer_operation(operation, Ok(OperationResult::BatchFound { found, failed: errors }));
+    }
+
+    fn handle_dht_deleted_key_response(&mut self, key_existed: bool, operation: Option<u64>) {
         if key_existed {
             self.storage.write_log_entry("Key deleted".to_string());
             info!("Key deleted");
@@ -477,29 +1694,534 @@ impl Node {
             self.storage.write_log_entry("Tried to delete key but the key was not present in the network".to_string());
             debug!("Tried to delete key but the key was not present in the network");
         }
+        self.answer_operation(operation, Ok(OperationResult::Deleted { key_existed }));
+    }
+
+    fn handle_dht_found_owner_response(&mut self, key_id: BigInt, owner: OtherNode, operation: Option<u64>) {
+        self.storage.write_log_entry(format!("Key_id {} is owned by node #{} ({})", key_id, owner.id, owner.ip_addr));
+        debug!("Key_id {} is owned by node #{} ({})", key_id, owner.id, owner.ip_addr);
+        self.answer_operation(operation, Ok(OperationResult::OwnerFound { key_id, owner }));
+    }
+
+    fn handle_dht_watched_key_response(&mut self, key_id: BigInt, current: Option<DHTEntry>) {
+        self.update_watched_key(key_id, current);
+    }
+
+    /// Reports a new version of a watched key in the storage log. Notifications of
+    /// keys that are not watched anymore and repeated versions are dropped.
+    fn update_watched_key(&mut self, key_id: BigInt, entry: Option<DHTEntry>) {
+        let (key, known_version) = match self.watched_keys.get_mut(&key_id) {
+            Some(watched) => watched,
+            None => return,
+        };
+        let version = entry.as_ref().map_or(0, |entry| entry.get_version());
+        if *known_version == Some(version) {
+            return;
+        }
+        let message = match (&known_version, &entry) {
+            (None, Some(entry)) => format!("Watching key '{}', value is {} (version {})", key, entry.get_value(), version),
+            (None, None) => format!("Watching key '{}', key is not present in the network", key),
+            (Some(_), Some(entry)) => format!("Key '{}' changed, value is {} (version {})", key, entry.get_value(), version),
+            (Some(_), None) => format!("Key '{}' was deleted", key),
+        };
+        *known_version = Some(version);
+        self.storage.write_log_entry(message.clone());
+        debug!("[Node #{}] {}", self.id, message);
+    }
+
+    fn handle_subscribed_response(&mut self, topic_id: BigInt) {
+        if let Some((topic, confirmed)) = self.subscribed_topics.get_mut(&topic_id) {
+            if !*confirmed {
+                *confirmed = true;
+                self.storage.write_log_entry(format!("Subscribed to topic '{}'", topic));
+                debug!("Subscribed to topic '{}'", topic);
+            }
+        }
+    }
+
+    fn handle_published_response(&mut self, topic: String, subscribers: usize) {
+        self.storage.write_log_entry(format!("Published to topic '{}', sent to {} subscribers", topic, subscribers));
+        debug!("Published to topic '{}', sent to {} subscribers", topic, subscribers);
+    }
+
+    fn handle_dht_took_over_keys_response(&mut self, request: Option<Request>) {
+        // Leases handed over to a new predecessor are not tracked, only the hand over on shutdown is
+        if let Some(Request::DHTTakeOverKeys { data, .. }) = request {
+            if self.state == NodeState::Draining {
+                info!("[Node #{}] Successor took over {} keys", self.id, data.len());
+                self.leave();
+            }
+        }
+    }
+
+    fn handle_dht_exported_keys_response(&mut self, sender: OtherNode, data: Vec<(BigInt, DHTEntry)>, successor: OtherNode) {
+        let next_node = match &mut self.pending_export {
+            Some(export) => export.collect(sender, data, successor),
+            None => return,
+        };
+        match next_node {
+            Some(next_node) => self.send_request(next_node, Request::DHTExportKeys),
+            None => self.pending_export = None,
+        }
+    }
+
+    fn handle_error_response(&mut self, error: ChordError, request: Option<Request>, operation: Option<u64>) {
+        match request {
+            Some(request @ Request::DHTStoreKeys { .. }) | Some(request @ Request::DHTFindKeys { .. }) => {
+                self.fail_batch_request(&request, &error.to_string(), operation);
+                return;
+            }
+            Some(Request::DHTExportKeys) => {
+                if let Some(export) = self.pending_export.take() {
+                    export.fail(error);
+                    return;
+                }
+            }
+            Some(request @ Request::DHTTakeOverKeys { .. }) => {
+                warn!("[Node #{}] Successor rejected the keys: {}", self.id, error);
+                self.report_failed_request(request, None);
+                return;
+            }
+            _ => {}
+        }
+        match error {
+            ChordError::IdCollision(..) if self.state == NodeState::Joining => self.handle_id_collision(error),
+            _ => {
+                self.storage.write_log_entry(format!("Request failed: {}", error));
+                self.answer_operation(operation, Err(error));
+            }
+        }
     }
 
-    fn handle_dht_ask_further_store_response(&self,
+    /// Picks a salted id for the next join attempt, or gives up if salting is disabled
+    fn handle_id_collision(&mut self, error: ChordError) {
+        if self.salt_id_on_collision {
+            self.id_salt += 1;
+            let id = chord::create_salted_node_id(self.ip_addr, self.id_salt);
+            warn!("[Node #{}] {}, retrying to join with salted ID #{}", self.id, error, id);
+            self.finger_table = FingerTable::new(id.clone());
+            self.id = id;
+        } else {
+            error!("[Node #{}] Unable to join: {}. Restart with --salt-id to join with a salted ID", self.id, error);
+            if let Err(e) = self.transition(NodeState::Failed) {
+                warn!("[Node #{}] {}", self.id, e);
+            }
+            self.outbox.push(Effect::Exit { code: 1 });
+        }
+    }
+
+    fn handle_dht_ask_further_store_response(&mut self,
                                              next_node: OtherNode,
-                                             data: (BigInt, DHTEntry)) {
+                                             data: (BigInt, DHTEntry),
+                                             operation: Option<u64>) {
         debug!("Did not store data {:?} yet, asking node #{} now...", data, next_node.id);
         let req = Request::DHTStoreKey { data };
-        network::send_request(self.to_other_node(),next_node.ip_addr,req);
+        self.send_operation_request(next_node, req, operation);
+    }
+
+    fn handle_dht_ask_further_store_if_response(&mut self,
+                                                next_node: OtherNode,
+                                                data: (BigInt, DHTEntry),
+                                                condition: WriteCondition,
+                                                operation: Option<u64>) {
+        debug!("Did not store data {:?} yet, asking node #{} now...", data, next_node.id);
+        let req = Request::DHTStoreKeyIf { data, condition };
+        self.send_operation_request(next_node, req, operation);
     }
 
-    fn handle_dht_ask_further_find_response(&self,
+    fn handle_dht_ask_further_find_response(&mut self,
                                             next_node: OtherNode,
-                                            key_id: BigInt) {
+                                            key_id: BigInt,
+                                            operation: Option<u64>) {
         debug!("Did not find key '{}' yet, asking node #{} now...", key_id, next_node.id);
         let req = Request::DHTFindKey { key_id };
-        network::send_request(self.to_other_node(), next_node.ip_addr, req);
+        self.send_operation_request(next_node, req, operation);
     }
 
-    fn handle_dht_ask_further_delete_response(&self,
+    fn handle_dht_ask_further_delete_response(&mut self,
                                               next_node: OtherNode,
-                                              key_id: BigInt) {
+                                              key_id: BigInt,
+                                              operation: Option<u64>) {
         debug!("Did not find key '{}' yet, asking node #{} now...", key_id, next_node.id);
         let req = Request::DHTDeleteKey { key_id };
-        network::send_request(self.to_other_node(), next_node.ip_addr, req);
+        self.send_operation_request(next_node, req, operation);
+    }
+
+    fn handle_dht_ask_further_find_owner_response(&mut self,
+                                                  next_node: OtherNode,
+                                                  key_id: BigInt,
+                                                  operation: Option<u64>) {
+        debug!("Did not find owner of key '{}' yet, asking node #{} now...", key_id, next_node.id);
+        let req = Request::DHTFindOwner { key_id };
+        self.send_operation_request(next_node, req, operation);
+    }
+
+    fn handle_dht_ask_further_watch_response(&mut self,
+                                             next_node: OtherNode,
+                                             key_id: BigInt) {
+        debug!("Did not find owner of key '{}' to watch it yet, asking node #{} now...", key_id, next_node.id);
+        let req = Request::DHTWatchKey { key_id };
+        self.send_request(next_node, req);
+    }
+
+    fn handle_ask_further_subscribe_response(&mut self,
+                                             next_node: OtherNode,
+                                             topic_id: BigInt) {
+        debug!("Did not find rendezvous node of topic '{}' yet, asking node #{} now...", topic_id, next_node.id);
+        let req = Request::Subscribe { topic_id };
+        self.send_request(next_node, req);
+    }
+
+    fn handle_ask_further_publish_response(&mut self,
+                                           next_node: OtherNode,
+                                           topic_id: BigInt,
+                                           topic: String,
+                                           message: String) {
+        debug!("Did not find rendezvous node of topic '{}' yet, asking node #{} now...", topic_id, next_node.id);
+        let req = Request::Publish { topic_id, topic, message };
+        self.send_request(next_node, req);
+    }
+}
+
+/// Takes the next id of a user operation or an imported entry
+fn create_operation_id(next_operation_id: &mut u64) -> u64 {
+    let operation = *next_operation_id;
+    *next_operation_id += 1;
+    operation
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::SocketAddr;
+    use std::sync::mpsc;
+
+    use num_bigint::BigInt;
+
+    use super::Node;
+    use super::super::batch::PendingBatch;
+    use super::super::chord;
+    use super::super::clock::ManualClock;
+    use super::super::lifecycle::NodeState;
+    use super::super::protocols::{Effect, Message, Request, Response};
+    use super::super::snapshot::ImportEvent;
+    use super::super::storage::{self, DHTEntry};
+
+    /// Nodes that exchange their messages directly, messages to dead nodes are dropped
+    struct Ring {
+        clock: ManualClock,
+        nodes: Vec<Node>,
+        dead: Vec<SocketAddr>,
+    }
+
+    impl Ring {
+        fn new(size: u16) -> Ring {
+            let clock = ManualClock::new();
+            let entry_point = address(0);
+            let first = Node::new_first(entry_point).with_clock(Box::new(clock.clone()));
+            let mut ring = Ring { clock, nodes: vec![first], dead: Vec::new() };
+            for index in 1..size {
+                ring.join(index);
+            }
+            ring
+        }
+
+        fn join(&mut self, index: u16) {
+            let mut node = Node::new(address(index), vec![address(0)], false).with_clock(Box::new(self.clock.clone()));
+            let effects = node.join(address(0));
+            self.nodes.push(node);
+            self.deliver(effects);
+            self.stabilize(5);
+            // Order the nodes along the ring, so tests can refer to successors by index
+            self.nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        }
+
+        fn kill(&mut self, index: usize) {
+            self.dead.push(self.nodes[index].ip_addr);
+        }
+
+        fn is_dead(&self, node: &Node) -> bool {
+            self.dead.contains(&node.ip_addr)
+        }
+
+        fn deliver(&mut self, effects: Vec<Effect>) {
+            let mut queue: VecDeque<Effect> = effects.into_iter().collect();
+            let mut delivered = 0;
+            while let Some(effect) = queue.pop_front() {
+                if let Effect::Send { target, message } = effect {
+                    if self.dead.contains(&target) {
+                        continue;
+                    }
+                    if let Some(node) = self.nodes.iter_mut().find(|node| node.ip_addr == target) {
+                        queue.extend(node.handle_message(*message));
+                    }
+                    delivered += 1;
+                    assert!(delivered < 100_000, "messages are sent in circles");
+                }
+            }
+        }
+
+        fn run<F>(&mut self, step: F) where F: Fn(&mut Node) -> Vec<Effect> {
+            for index in 0..self.nodes.len() {
+                if self.is_dead(&self.nodes[index]) {
+                    continue;
+                }
+                let effects = step(&mut self.nodes[index]);
+                self.deliver(effects);
+            }
+        }
+
+        /// Runs the periodic tasks of all living nodes, one ping interval per round
+        fn stabilize(&mut self, rounds: usize) {
+            for _ in 0..rounds {
+                self.clock.advance(chord::NODE_PING_INTERVAL);
+                self.run(|node| node.ping_peers());
+                self.run(|node| node.check_predecessor());
+                self.run(|node| node.stabilize());
+                for _ in 0..chord::FINGERTABLE_SIZE {
+                    self.run(|node| node.fix_next_finger());
+                }
+            }
+        }
+
+        fn assert_consistent(&self) {
+            let living: Vec<&Node> = self.nodes.iter().filter(|node| !self.is_dead(node)).collect();
+            for (index, node) in living.iter().enumerate() {
+                let successor = living[(index + 1) % living.len()];
+                let predecessor = living[(index + living.len() - 1) % living.len()];
+                assert_eq!(node.state, NodeState::Active);
+                assert_eq!(node.get_successor().id, successor.id, "successor of node #{}", node.id);
+                assert_eq!(node.predecessor.as_ref().map(|pre| pre.id.clone()), Some(predecessor.id.clone()),
+                           "predecessor of node #{}", node.id);
+            }
+        }
+
+        /// Node responsible for the key id
+        fn owner(&self, key_id: &BigInt) -> usize {
+            let count = self.nodes.len();
+            (0..count)
+                .find(|index| chord::is_my_key(&self.nodes[*index].id, &self.nodes[(index + count - 1) % count].id, key_id))
+                .unwrap()
+        }
+    }
+
+    fn address(index: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 40000 + index))
+    }
+
+    fn key_of(ring: &Ring, owner: usize) -> String {
+        (0..).map(|i| format!("key-{}", i))
+            .find(|key| ring.owner(&chord::create_id(key)) == owner)
+            .unwrap()
+    }
+
+    fn targets(effects: &[Effect]) -> Vec<SocketAddr> {
+        effects.iter()
+            .filter_map(|effect| match effect {
+                Effect::Send { target, message } if matches!(**message, Message::RequestMessage { .. }) => Some(*target),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn join_notify_and_stabilize_form_a_ring() {
+        let ring = Ring::new(4);
+        ring.assert_consistent();
+        for node in &ring.nodes {
+            let expected: Vec<BigInt> = (1..ring.nodes.len())
+                .map(|offset| ring.nodes[(ring.nodes.iter().position(|n| n.id == node.id).unwrap() + offset) % ring.nodes.len()].id.clone())
+                .collect();
+            let successors: Vec<BigInt> = node.successor_list.iter().take(expected.len()).map(|succ| succ.id.clone()).collect();
+            assert_eq!(successors, expected, "successor list of node #{}", node.id);
+        }
+    }
+
+    #[test]
+    fn failed_successor_is_replaced_by_the_next_one() {
+        let mut ring = Ring::new(4);
+        ring.kill(1);
+        ring.stabilize(10);
+        ring.assert_consistent();
+        assert_eq!(ring.nodes[0].get_successor().id, ring.nodes[2].id);
+        assert_eq!(ring.nodes[2].predecessor.as_ref().unwrap().id, ring.nodes[0].id);
+    }
+
+    #[test]
+    fn keys_are_handed_over_on_shutdown() {
+        let mut ring = Ring::new(2);
+        let key = key_of(&ring, 0);
+        let (key_id, entry) = storage::make_hashed_key_value_pair(key, "value".to_string(), None);
+        let effects = ring.nodes[0].send_user_request(Request::DHTStoreKey { data: (key_id.clone(), entry) }, None);
+        ring.deliver(effects);
+        let version = ring.nodes[0].storage.get_key(&key_id).unwrap().get_version();
+
+        let effects = ring.nodes[0].graceful_shutdown();
+        assert_eq!(ring.nodes[0].state, NodeState::Draining);
+        assert!(!effects.iter().any(|effect| matches!(effect, Effect::Exit { .. })));
+        ring.deliver(effects);
+        assert_eq!(ring.nodes[0].state, NodeState::Leaving);
+
+        let handed_over = ring.nodes[1].storage.get_key(&key_id).unwrap();
+        assert_eq!(handed_over.get_value(), "value");
+        assert_eq!(handed_over.get_version(), version);
+    }
+
+    #[test]
+    fn deleted_keys_stay_deleted_after_the_hand_over() {
+        let mut ring = Ring::new(2);
+        let key = key_of(&ring, 0);
+        let data = storage::make_hashed_key_value_pair(key, "value".to_string(), None);
+        let key_id = data.0.clone();
+        let effects = ring.nodes[0].send_user_request(Request::DHTStoreKey { data }, None);
+        ring.deliver(effects);
+        let stale = ring.nodes[0].storage.get_key(&key_id).unwrap();
+        let effects = ring.nodes[0].send_user_request(Request::DHTDeleteKey { key_id: key_id.clone() }, None);
+        ring.deliver(effects);
+        assert!(ring.nodes[0].storage.get_key(&key_id).is_none());
+
+        let effects = ring.nodes[0].graceful_shutdown();
+        ring.deliver(effects);
+        assert_eq!(ring.nodes[0].state, NodeState::Leaving);
+
+        // An older copy of the key that arrives after the hand over, e.g. from a redistribution
+        let sender = ring.nodes[0].to_other_node();
+        let request = Request::DHTTakeOverKeys {
+            data: vec![(key_id.clone(), stale)], tombstones: Vec::new(), watches: Vec::new(), subscriptions: Vec::new(),
+        };
+        ring.nodes[1].handle_message(Message::RequestMessage { sender, request_id: 1, request });
+        assert!(ring.nodes[1].storage.get_key(&key_id).is_none());
+    }
+
+    #[test]
+    fn draining_node_leaves_when_the_successor_does_not_answer() {
+        let mut ring = Ring::new(2);
+        let data = storage::make_hashed_key_value_pair(key_of(&ring, 0), "value".to_string(), None);
+        let effects = ring.nodes[0].send_user_request(Request::DHTStoreKey { data }, None);
+        ring.deliver(effects);
+        ring.kill(1);
+
+        ring.nodes[0].graceful_shutdown();
+        let mut exited = false;
+        for _ in 0..7 {
+            ring.clock.advance(chord::REQUEST_TIMEOUT_INTERVAL);
+            let effects = ring.nodes[0].check_pending_requests();
+            exited |= effects.iter().any(|effect| matches!(effect, Effect::Exit { code: 0 }));
+        }
+        assert!(exited);
+        assert_eq!(ring.nodes[0].state, NodeState::Leaving);
+    }
+
+    #[test]
+    fn handed_over_keys_are_taken_over_while_joining() {
+        let sender = Node::new_first(address(0)).to_other_node();
+        let mut node = Node::new(address(1), vec![address(0)], false);
+        let (key_id, entry) = storage::make_hashed_key_value_pair("key".to_string(), "value".to_string(), None);
+        let request = Request::DHTTakeOverKeys {
+            data: vec![(key_id.clone(), entry)], tombstones: Vec::new(), watches: Vec::new(), subscriptions: Vec::new(),
+        };
+        let effects = node.handle_message(Message::RequestMessage { sender, request_id: 1, request });
+
+        assert_eq!(node.state, NodeState::Joining);
+        assert!(node.storage.get_key(&key_id).is_some());
+        assert!(matches!(&effects[..], [Effect::Send { message, .. }]
+            if matches!(**message, Message::ResponseMessage { response: Response::DHTTookOverKeys, .. })));
+    }
+
+    #[test]
+    fn keys_move_to_a_joining_node() {
+        let mut ring = Ring::new(1);
+        let keys: Vec<String> = (0..20).map(|i| format!("key-{}", i)).collect();
+        for key in &keys {
+            let data = storage::make_hashed_key_value_pair(key.clone(), "value".to_string(), None);
+            let effects = ring.nodes[0].send_user_request(Request::DHTStoreKey { data }, None);
+            ring.deliver(effects);
+        }
+        ring.join(1);
+        ring.assert_consistent();
+
+        for key in &keys {
+            let key_id = chord::create_id(key);
+            let owner = ring.owner(&key_id);
+            assert!(ring.nodes[owner].storage.get_key(&key_id).is_some(), "key '{}' is missing at its owner", key);
+            assert!(ring.nodes[1 - owner].storage.get_key(&key_id).is_none(), "key '{}' is still stored by another node", key);
+        }
+    }
+
+    #[test]
+    fn imported_entries_are_answered_by_their_operation() {
+        let mut ring = Ring::new(2);
+        let key = key_of(&ring, 1);
+        let key_id = chord::create_id(&key);
+        let (events, receiver) = mpsc::channel();
+        let imported = DHTEntry::with_version(key.clone(), "old".to_string(), 1, None);
+        let import_effects = ring.nodes[0].import_keys(vec![(key_id.clone(), imported)], events);
+
+        // A put of the same key that is answered while the imported entry is in flight
+        for value in &["new", "newer"] {
+            let data = storage::make_hashed_key_value_pair(key.clone(), value.to_string(), None);
+            let effects = ring.nodes[0].send_user_request(Request::DHTStoreKey { data }, None);
+            ring.deliver(effects);
+        }
+        assert!(receiver.try_recv().is_err());
+
+        ring.deliver(import_effects);
+        assert!(matches!(receiver.try_recv(), Ok(ImportEvent::Skipped)));
+        assert!(ring.nodes[0].pending_import.is_none());
+        assert_eq!(ring.nodes[1].storage.get_key(&key_id).unwrap().get_value(), "newer");
+        let result = ring.nodes[0].storage.get_log_entries().back().unwrap().get_message().clone();
+        assert!(result.starts_with(&format!("Key '{}' stored", key)), "unexpected result: {}", result);
+    }
+
+    #[test]
+    fn unanswered_request_is_retried_then_rerouted_per_key() {
+        let mut ring = Ring::new(4);
+        let (dead, third, fourth) = (ring.nodes[1].to_other_node(), ring.nodes[2].ip_addr, ring.nodes[3].ip_addr);
+        // Keys of the third and fourth node, which the first node could only ask its successor for
+        let key_ids = vec![ring.nodes[2].id.clone(), ring.nodes[3].id.clone()];
+        ring.kill(1);
+        {
+            let node = &mut ring.nodes[0];
+            node.pending_finds.insert(7, PendingBatch::new(key_ids.clone()));
+            node.send_request(dead.clone(), Request::DHTFindKeys { batch_id: 7, key_ids });
+            assert_eq!(targets(&node.take_effects()), vec![dead.ip_addr]);
+        }
+
+        // The timeout doubles with every retry
+        let mut sent = Vec::new();
+        for _ in 0..7 {
+            ring.clock.advance(chord::REQUEST_TIMEOUT_INTERVAL);
+            let effects = ring.nodes[0].check_pending_requests();
+            sent.extend(targets(&effects));
+            ring.deliver(effects);
+        }
+        assert_eq!(&sent[..2], &[dead.ip_addr, dead.ip_addr]);
+        let mut rerouted = sent[2..].to_vec();
+        rerouted.sort();
+        let mut expected = vec![third, fourth];
+        expected.sort();
+        assert_eq!(rerouted, expected);
+
+        assert!(ring.nodes[0].pending_finds.is_empty());
+        let result = ring.nodes[0].storage.get_log_entries().back().unwrap().get_message().clone();
+        assert!(result.starts_with("Batch 7:"), "unexpected result: {}", result);
+    }
+
+    #[test]
+    fn batch_keys_without_a_node_left_are_reported_as_failed() {
+        let mut ring = Ring::new(2);
+        let dead = ring.nodes[1].to_other_node();
+        ring.kill(1);
+        let node = &mut ring.nodes[0];
+        node.pending_finds.insert(7, PendingBatch::new(vec![dead.id.clone()]));
+        node.send_request(dead.clone(), Request::DHTFindKeys { batch_id: 7, key_ids: vec![dead.id.clone()] });
+        node.take_effects();
+        for _ in 0..7 {
+            ring.clock.advance(chord::REQUEST_TIMEOUT_INTERVAL);
+            node.check_pending_requests();
+        }
+        assert!(node.pending_finds.is_empty());
+        let result = node.storage.get_log_entries().back().unwrap().get_message().clone();
+        assert!(result.contains("failed"), "unexpected result: {}", result);
     }
 }
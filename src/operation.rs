@@ -0,0 +1,69 @@
+use std::sync::mpsc::Sender;
+
+use num_bigint::BigInt;
+
+use super::error::ChordError;
+use super::node::OtherNode;
+use super::storage::DHTEntry;
+
+/// Result of a DHT request of the user, sent to the frontend that is waiting for it
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum OperationResult {
+    Stored(StoredKey),
+    /// Result of a store that was not applied, the version is the newer one the owner keeps
+    Kept(StoredKey),
+    /// Result of `putnx` and `cas`, the version and value are the current ones if the write was not applied
+    ConditionalStored {
+        key: String,
+        applied: bool,
+        version: u64,
+        current_value: Option<String>,
+    },
+    Found(FoundKey),
+    Deleted {
+        key_existed: bool,
+    },
+    OwnerFound {
+        key_id: BigInt,
+        owner: OtherNode,
+    },
+    BatchStored {
+        stored: Vec<StoredKey>,
+        failed: Vec<String>,
+    },
+    BatchFound {
+        found: Vec<FoundKey>,
+        failed: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoredKey {
+    pub key: String,
+    pub version: u64,
+}
+
+/// Value of a key, the key itself is only known if it was found
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FoundKey {
+    pub key_id: BigInt,
+    pub found: bool,
+    pub key: Option<String>,
+    pub value: Option<String>,
+    pub version: Option<u64>,
+}
+
+impl FoundKey {
+    pub fn new(key_id: BigInt, entry: Option<&DHTEntry>) -> FoundKey {
+        FoundKey {
+            key_id,
+            found: entry.is_some(),
+            key: entry.map(|entry| entry.get_key().clone()),
+            value: entry.map(|entry| entry.get_value().clone()),
+            version: entry.map(|entry| entry.get_version()),
+        }
+    }
+}
+
+/// Receives the result of a request once the node got the last response for it
+pub type OperationReply = Sender<Result<OperationResult, ChordError>>;
@@ -0,0 +1,88 @@
+use std::time::Instant;
+
+use num_bigint::BigInt;
+
+use super::chord;
+use super::node::OtherNode;
+use super::protocols::Request;
+
+/// Request that was sent by this node and is still waiting for its response
+#[derive(Clone)]
+pub struct PendingRequest {
+    target: OtherNode,
+    request: Request,
+    attempt: u32,
+    deadline: Instant,
+    // Nodes that did not answer this request in time
+    failed_nodes: Vec<BigInt>,
+    // User operation the response is the result of, carried over to the next node asked
+    operation: Option<u64>,
+}
+
+impl PendingRequest {
+    pub fn new(target: OtherNode, request: Request, now: Instant) -> PendingRequest {
+        PendingRequest {
+            target,
+            request,
+            attempt: 0,
+            deadline: now + chord::REQUEST_TIMEOUT_INTERVAL,
+            failed_nodes: Vec::new(),
+            operation: None,
+        }
+    }
+
+    pub fn for_operation(mut self, operation: Option<u64>) -> PendingRequest {
+        self.operation = operation;
+        self
+    }
+
+    pub fn get_target(&self) -> &OtherNode {
+        &self.target
+    }
+
+    pub fn get_request(&self) -> &Request {
+        &self.request
+    }
+
+    pub fn into_request(self) -> Request {
+        self.request
+    }
+
+    pub fn get_operation(&self) -> Option<u64> {
+        self.operation
+    }
+
+    pub fn get_failed_nodes(&self) -> &Vec<BigInt> {
+        &self.failed_nodes
+    }
+
+    pub fn is_expired(&self, now: Instant) -> bool {
+        self.deadline <= now
+    }
+
+    pub fn has_retries_left(&self) -> bool {
+        self.attempt < chord::REQUEST_MAX_RETRIES
+    }
+
+    /// Prepares the next attempt on the same node, doubling the timeout each time
+    pub fn retry(&mut self, now: Instant) {
+        self.attempt += 1;
+        self.deadline = now + chord::REQUEST_TIMEOUT_INTERVAL * 2u32.pow(self.attempt);
+    }
+
+    /// Request for a part of this request, e.g. some keys of a batch, that is sent to another node
+    /// because the current target did not answer. The failed nodes are carried over.
+    pub fn split(&self, target: OtherNode, request: Request, now: Instant) -> PendingRequest {
+        let mut failed_nodes = self.failed_nodes.clone();
+        failed_nodes.push(self.target.get_id().clone());
+        PendingRequest { failed_nodes, operation: self.operation, ..PendingRequest::new(target, request, now) }
+    }
+
+    /// Gives up on the current target and prepares sending the request to another node
+    pub fn reroute(&mut self, target: OtherNode, now: Instant) {
+        self.failed_nodes.push(self.target.get_id().clone());
+        self.target = target;
+        self.attempt = 0;
+        self.deadline = now + chord::REQUEST_TIMEOUT_INTERVAL;
+    }
+}
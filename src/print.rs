@@ -18,16 +18,21 @@ pub fn print_current_node_state(node: &Node) {
     let mut node_info_table = table!(["Descr.".italic().yellow(), "ID".italic().yellow(), "SocketAddr".italic().yellow()],["", "", ""],
                     ["Predecessor", &pre_id_string, &pre_ip_string],
                     ["Self".green(), node.get_id().clone().to_string().green(), node.get_ip_addr().to_string().green()],
-                    ["Successor",  &succ_id_string, &succ_ip_string]);
+                    ["Successor",  &succ_id_string, &succ_ip_string],
+                    ["State", node.get_state().to_string(), ""]);
     // node_info_table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     node_info_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
 
 
     // Successor List
-    let mut successor_list_table = table!(["#".italic().yellow(), "ID".italic().yellow(), "SocketAddr".italic().yellow()],["", "", ""]);
+    let mut successor_list_table = table!(["#".italic().yellow(), "ID".italic().yellow(), "SocketAddr".italic().yellow(), "RTT".italic().yellow()],["", "", "", ""]);
     for i in 0..node.get_successor_list().len() {
         let succ = &node.get_successor_list()[i];
-        successor_list_table.add_row(row![r -> &i.to_string(), &succ.get_id().clone().to_string(), &succ.get_ip_addr().clone().to_string()]);
+        let rtt_string = match node.get_failure_detector().get_rtt(succ.get_id()) {
+            Some(rtt) => format!("{}ms", rtt.as_secs() * 1000 + u64::from(rtt.subsec_millis())),
+            None => "-".to_string(),
+        };
+        successor_list_table.add_row(row![r -> &i.to_string(), &succ.get_id().clone().to_string(), &succ.get_ip_addr().clone().to_string(), r -> rtt_string]);
     }
     // successor_list_table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
     successor_list_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
@@ -44,11 +49,11 @@ pub fn print_current_node_state(node: &Node) {
 
     // Storage
     let mut storage_table = table!(
-        ["ID ".italic().yellow(), "Key".italic().yellow(), "Value".italic().yellow()],
-        ["", "", ""]
+        ["ID ".italic().yellow(), "Key".italic().yellow(), "Value".italic().yellow(), "Version".italic().yellow()],
+        ["", "", "", ""]
         );
     for (key_id, dht_entry) in node.get_storage().get_data_as_iter() {
-        storage_table.add_row(row![&key_id.clone().to_string(), dht_entry.get_key(), dht_entry.get_value()]);
+        storage_table.add_row(row![&key_id.clone().to_string(), dht_entry.get_key(), dht_entry.get_value(), r -> dht_entry.get_version().to_string()]);
     }
     storage_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
 
@@ -68,6 +73,16 @@ pub fn print_current_node_state(node: &Node) {
         storage_logs_table.add_row(row![item.clone().to_string()]);
     }
     storage_logs_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
+
+    let rejected_traffic: Vec<String> = node.get_traffic_stats().get_all().iter()
+        .map(|(rejection, count)| format!("{}: {}", rejection, count))
+        .collect();
+    let mut traffic_table = table!(
+                    ["> Rejected traffic ".black().on_white()],
+                    [""],
+                    [rejected_traffic.join(", ")]
+                    );
+    traffic_table.set_format(*format::consts::FORMAT_BORDERS_ONLY);
     //print!("{}[2J", 27 as char);
-    info!("\n\n{}\n{}{}", "INFO! Open menu by typing 'm+ENTER'".to_string().black().on_white(), state_table, storage_logs_table);
+    info!("\n\n{}{}{}", state_table, storage_logs_table, traffic_table);
 }
\ No newline at end of file
@@ -1,24 +1,64 @@
+use std::net::SocketAddr;
+
 use num_bigint::BigInt;
 
+use super::error::ChordError;
 use super::node::OtherNode;
-use super::storage::DHTEntry;
+use super::storage::{DHTEntry, Lease, Tombstone, WriteCondition};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Message {
     Kill,
+    /// `timestamp` is taken from the clock of the pinging node and echoed in the `Pong`,
+    /// so the round trip time can be measured without keeping state
     Ping {
-        sender: OtherNode
+        sender: OtherNode,
+        timestamp: u64,
+    },
+    Pong {
+        sender: OtherNode,
+        timestamp: u64,
     },
+    /// `request_id` is chosen by the sender and echoed in the response,
+    /// `0` marks a request that is not tracked by the sender
     RequestMessage {
         sender: OtherNode,
+        request_id: u64,
         request: Request,
     },
     ResponseMessage {
         sender: OtherNode,
+        request_id: u64,
         response: Response,
     },
 }
 
+impl Message {
+    /// Name of the message for logging, requests and responses are named by their content
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            Message::Kill => "Kill",
+            Message::Ping { .. } => "Ping",
+            Message::Pong { .. } => "Pong",
+            Message::RequestMessage { request, .. } => request.get_type(),
+            Message::ResponseMessage { response, .. } => response.get_type(),
+        }
+    }
+}
+
+/// Side effect of the node logic, which is executed by the actor afterwards
+#[derive(Clone, Debug)]
+pub enum Effect {
+    /// Boxed, as messages are much larger than the other effects
+    Send {
+        target: SocketAddr,
+        message: Box<Message>,
+    },
+    /// Terminate the process once all previous effects are executed
+    Exit {
+        code: i32,
+    },
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum Request {
@@ -37,14 +77,139 @@ pub enum Request {
     DHTStoreKey {
         data: (BigInt, DHTEntry)
     },
+    DHTStoreKeyIf {
+        data: (BigInt, DHTEntry),
+        condition: WriteCondition,
+    },
+    DHTStoreKeys {
+        batch_id: u64,
+        data: Vec<(BigInt, DHTEntry)>,
+    },
     DHTFindKey {
         key_id: BigInt
     },
+    DHTFindKeys {
+        batch_id: u64,
+        key_ids: Vec<BigInt>,
+    },
     DHTDeleteKey {
         key_id: BigInt
     },
+    /// Asks for the node responsible for the key, without reading the key
+    DHTFindOwner {
+        key_id: BigInt
+    },
+    /// Registers the sender as watcher of the key, or renews its lease
+    DHTWatchKey {
+        key_id: BigInt
+    },
+    /// Notifies a watcher that the key was written or deleted (`None`), not answered
+    DHTKeyChanged {
+        key_id: BigInt,
+        entry: Option<DHTEntry>,
+    },
+    /// Registers the sender as subscriber of the topic at its rendezvous node, or renews its lease
+    Subscribe {
+        topic_id: BigInt
+    },
+    /// Sends the message to all subscribers of the topic via its rendezvous node
+    Publish {
+        topic_id: BigInt,
+        topic: String,
+        message: String,
+    },
+    /// Delivers a published message to a subscriber, not answered
+    Deliver {
+        topic_id: BigInt,
+        topic: String,
+        message: String,
+    },
     DHTTakeOverKeys {
-        data: Vec<(BigInt, DHTEntry)>
+        data: Vec<(BigInt, DHTEntry)>,
+        /// Deletes of the handed over keys, so older copies that are still in flight stay deleted
+        tombstones: Vec<(BigInt, Tombstone)>,
+        watches: Vec<(BigInt, Lease)>,
+        subscriptions: Vec<(BigInt, Lease)>,
+    },
+    /// Asks a specific node for all keys it stores, used to walk the ring for an export
+    DHTExportKeys,
+}
+
+impl Request {
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            Request::FindSuccessor { .. } => "FindSuccessor",
+            Request::GetPredecessor => "GetPredecessor",
+            Request::Notify { .. } => "Notify",
+            Request::FindSuccessorFinger { .. } => "FindSuccessorFinger",
+            Request::GetSuccessorList => "GetSuccessorList",
+            Request::DHTStoreKey { .. } => "DHTStoreKey",
+            Request::DHTStoreKeyIf { .. } => "DHTStoreKeyIf",
+            Request::DHTStoreKeys { .. } => "DHTStoreKeys",
+            Request::DHTFindKey { .. } => "DHTFindKey",
+            Request::DHTFindKeys { .. } => "DHTFindKeys",
+            Request::DHTDeleteKey { .. } => "DHTDeleteKey",
+            Request::DHTFindOwner { .. } => "DHTFindOwner",
+            Request::DHTWatchKey { .. } => "DHTWatchKey",
+            Request::DHTKeyChanged { .. } => "DHTKeyChanged",
+            Request::Subscribe { .. } => "Subscribe",
+            Request::Publish { .. } => "Publish",
+            Request::Deliver { .. } => "Deliver",
+            Request::DHTTakeOverKeys { .. } => "DHTTakeOverKeys",
+            Request::DHTExportKeys => "DHTExportKeys",
+        }
+    }
+
+    /// Id that is used to route the request through the ring, `None` if the request
+    /// is addressed to a specific node
+    pub fn get_routing_id(&self) -> Option<&BigInt> {
+        match self {
+            Request::FindSuccessor { id } => Some(id),
+            Request::FindSuccessorFinger { finger_id, .. } => Some(finger_id),
+            Request::DHTStoreKey { data } => Some(&data.0),
+            Request::DHTStoreKeyIf { data, .. } => Some(&data.0),
+            Request::DHTStoreKeys { data, .. } => data.first().map(|(key_id, _)| key_id),
+            Request::DHTFindKey { key_id } => Some(key_id),
+            Request::DHTFindKeys { key_ids, .. } => key_ids.first(),
+            Request::DHTDeleteKey { key_id } => Some(key_id),
+            Request::DHTFindOwner { key_id } => Some(key_id),
+            Request::DHTWatchKey { key_id } => Some(key_id),
+            Request::Subscribe { topic_id } => Some(topic_id),
+            Request::Publish { topic_id, .. } => Some(topic_id),
+            Request::GetPredecessor
+            | Request::Notify { .. }
+            | Request::GetSuccessorList
+            | Request::DHTKeyChanged { .. }
+            | Request::Deliver { .. }
+            | Request::DHTTakeOverKeys { .. }
+            | Request::DHTExportKeys => None,
+        }
+    }
+
+    /// Whether the request reads or writes keys, which only an active node may answer.
+    /// Keys that are handed over are not, they are taken over in any state.
+    pub fn is_key_request(&self) -> bool {
+        match self {
+            Request::FindSuccessor { .. }
+            | Request::FindSuccessorFinger { .. }
+            | Request::GetPredecessor
+            | Request::Notify { .. }
+            | Request::GetSuccessorList
+            | Request::DHTKeyChanged { .. }
+            | Request::Deliver { .. }
+            | Request::DHTTakeOverKeys { .. } => false,
+            Request::DHTStoreKey { .. }
+            | Request::DHTStoreKeyIf { .. }
+            | Request::DHTStoreKeys { .. }
+            | Request::DHTFindKey { .. }
+            | Request::DHTFindKeys { .. }
+            | Request::DHTDeleteKey { .. }
+            | Request::DHTFindOwner { .. }
+            | Request::DHTWatchKey { .. }
+            | Request::Subscribe { .. }
+            | Request::Publish { .. }
+            | Request::DHTExportKeys => true,
+        }
     }
 }
 
@@ -73,19 +238,67 @@ pub enum Response {
     GetSuccessorListResponse {
         successor_list: Vec<OtherNode>
     },
-    DHTStoredKey{
+    /// `applied` is false if the owner kept a newer version, which `version` is then
+    DHTStoredKey {
+        key: String,
+        version: u64,
+        applied: bool,
+    },
+    DHTConditionalStoredKey {
         key: String,
+        applied: bool,
+        version: u64,
+        current: Option<DHTEntry>,
+    },
+    DHTStoredKeys {
+        batch_id: u64,
+        stored: Vec<(BigInt, String, u64)>,
+        ask_further: Vec<(OtherNode, Vec<(BigInt, DHTEntry)>)>,
     },
     DHTFoundKey {
         data: (BigInt, Option<DHTEntry>)
     },
+    DHTFoundKeys {
+        batch_id: u64,
+        data: Vec<(BigInt, Option<DHTEntry>)>,
+        ask_further: Vec<(OtherNode, Vec<BigInt>)>,
+    },
     DHTDeletedKey {
         key_existed: bool
     },
+    DHTFoundOwner {
+        key_id: BigInt,
+        owner: OtherNode,
+    },
+    /// The watch is registered, `current` is the entry at that moment
+    DHTWatchedKey {
+        key_id: BigInt,
+        current: Option<DHTEntry>,
+    },
+    Subscribed {
+        topic_id: BigInt,
+    },
+    /// The message was delivered to `subscribers` nodes
+    Published {
+        topic: String,
+        subscribers: usize,
+    },
+    /// The handed over keys are merged into the storage
+    DHTTookOverKeys,
+    /// All keys of the node and its successor, the next node of the walk
+    DHTExportedKeys {
+        data: Vec<(BigInt, DHTEntry)>,
+        successor: OtherNode,
+    },
     DHTAskFurtherStore {
         next_node: OtherNode,
         data: (BigInt, DHTEntry),
     },
+    DHTAskFurtherStoreIf {
+        next_node: OtherNode,
+        data: (BigInt, DHTEntry),
+        condition: WriteCondition,
+    },
     DHTAskFurtherFind {
         next_node: OtherNode,
         key_id: BigInt,
@@ -94,5 +307,60 @@ pub enum Response {
         next_node: OtherNode,
         key_id: BigInt,
     },
+    DHTAskFurtherFindOwner {
+        next_node: OtherNode,
+        key_id: BigInt,
+    },
+    DHTAskFurtherWatch {
+        next_node: OtherNode,
+        key_id: BigInt,
+    },
+    AskFurtherSubscribe {
+        next_node: OtherNode,
+        topic_id: BigInt,
+    },
+    AskFurtherPublish {
+        next_node: OtherNode,
+        topic_id: BigInt,
+        topic: String,
+        message: String,
+    },
+    Error {
+        error: ChordError,
+    },
 }
 
+impl Response {
+    pub fn get_type(&self) -> &'static str {
+        match self {
+            Response::FoundSuccessor { .. } => "FoundSuccessor",
+            Response::AskFurther { .. } => "AskFurther",
+            Response::GetPredecessorResponse { .. } => "GetPredecessorResponse",
+            Response::NotifyResponse => "NotifyResponse",
+            Response::FoundSuccessorFinger { .. } => "FoundSuccessorFinger",
+            Response::AskFurtherFinger { .. } => "AskFurtherFinger",
+            Response::GetSuccessorListResponse { .. } => "GetSuccessorListResponse",
+            Response::DHTStoredKey { .. } => "DHTStoredKey",
+            Response::DHTConditionalStoredKey { .. } => "DHTConditionalStoredKey",
+            Response::DHTStoredKeys { .. } => "DHTStoredKeys",
+            Response::DHTFoundKey { .. } => "DHTFoundKey",
+            Response::DHTFoundKeys { .. } => "DHTFoundKeys",
+            Response::DHTDeletedKey { .. } => "DHTDeletedKey",
+            Response::DHTFoundOwner { .. } => "DHTFoundOwner",
+            Response::DHTWatchedKey { .. } => "DHTWatchedKey",
+            Response::Subscribed { .. } => "Subscribed",
+            Response::Published { .. } => "Published",
+            Response::DHTTookOverKeys => "DHTTookOverKeys",
+            Response::DHTExportedKeys { .. } => "DHTExportedKeys",
+            Response::DHTAskFurtherStore { .. } => "DHTAskFurtherStore",
+            Response::DHTAskFurtherStoreIf { .. } => "DHTAskFurtherStoreIf",
+            Response::DHTAskFurtherFind { .. } => "DHTAskFurtherFind",
+            Response::DHTAskFurtherDelete { .. } => "DHTAskFurtherDelete",
+            Response::DHTAskFurtherFindOwner { .. } => "DHTAskFurtherFindOwner",
+            Response::DHTAskFurtherWatch { .. } => "DHTAskFurtherWatch",
+            Response::AskFurtherSubscribe { .. } => "AskFurtherSubscribe",
+            Response::AskFurtherPublish { .. } => "AskFurtherPublish",
+            Response::Error { .. } => "Error",
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+use super::actor::Command;
+use super::chord;
+use super::error::ChordError;
+use super::input;
+use super::protocols::Request;
+use super::snapshot;
+use super::storage;
+use super::storage::WriteCondition;
+
+/// Line of the transcript, printed as one JSON object per executed command
+#[derive(Serialize, Debug)]
+struct TranscriptEntry<'a> {
+    line: usize,
+    command: &'a str,
+    outcome: Outcome,
+    result: Value,
+    elapsed_ms: u64,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Ok,
+    Error,
+    Timeout,
+}
+
+/// Executes the commands of the script file (stdin for `-`) one after another and prints a transcript.
+/// Empty lines and lines starting with `#` are skipped.
+pub fn run(path: &str, actor: &Sender<Command>) -> Result<(), ChordError> {
+    let reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(stdin()))
+    } else {
+        Box::new(BufReader::new(File::open(path)?))
+    };
+    info!("Running script {}", path);
+
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        let command = line.trim();
+        if command.is_empty() || command.starts_with('#') {
+            continue;
+        }
+        let started = Instant::now();
+        let (outcome, result) = match execute(command, actor) {
+            Ok(result) => (Outcome::Ok, result),
+            Err(ChordError::Timeout) => (Outcome::Timeout, Value::Null),
+            Err(e) => (Outcome::Error, Value::String(e.to_string())),
+        };
+        let elapsed = started.elapsed();
+        let entry = TranscriptEntry {
+            line: index + 1,
+            command,
+            outcome,
+            result,
+            elapsed_ms: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+        };
+        let stdout = stdout();
+        let mut handle = stdout.lock();
+        writeln!(handle, "{}", serde_json::to_string(&entry)?)?;
+        handle.flush()?;
+        if command == "shutdown" {
+            break;
+        }
+    }
+    info!("Script {} finished", path);
+    Ok(())
+}
+
+/// Executes a single command, DHT commands wait for their result
+fn execute(command: &str, actor: &Sender<Command>) -> Result<Value, ChordError> {
+    let words = input::split_words(command)?;
+    let args = &words[1..];
+    match (words[0].as_str(), args.len()) {
+        ("put", 2) | ("put", 3) => {
+            let data = storage::make_hashed_key_value_pair(args[0].clone(), args[1].clone(), input::parse_ttl(args.get(2))?);
+            execute_request(actor, Request::DHTStoreKey { data })
+        }
+        ("putnx", 2) | ("putnx", 3) => {
+            let data = storage::make_hashed_key_value_pair(args[0].clone(), args[1].clone(), input::parse_ttl(args.get(2))?);
+            execute_request(actor, Request::DHTStoreKeyIf { data, condition: WriteCondition::IfAbsent })
+        }
+        ("cas", 3) | ("cas", 4) => {
+            let condition = WriteCondition::IfVersion(input::parse_version(&args[1])?);
+            let data = storage::make_hashed_key_value_pair(args[0].clone(), args[2].clone(), input::parse_ttl(args.get(3))?);
+            execute_request(actor, Request::DHTStoreKeyIf { data, condition })
+        }
+        ("mput", n) if n > 0 => {
+            let data = input::parse_pairs(args)?.into_iter()
+                .map(|(key, value)| storage::make_hashed_key_value_pair(key, value, None))
+                .collect();
+            execute_request(actor, Request::DHTStoreKeys { batch_id: input::create_batch_id(), data })
+        }
+        ("get", 1) => execute_request(actor, Request::DHTFindKey { key_id: chord::create_id(&args[0]) }),
+        ("mget", n) if n > 0 => {
+            let key_ids = args.iter().map(|key| chord::create_id(key)).collect();
+            execute_request(actor, Request::DHTFindKeys { batch_id: input::create_batch_id(), key_ids })
+        }
+        ("del", 1) => execute_request(actor, Request::DHTDeleteKey { key_id: chord::create_id(&args[0]) }),
+        ("owner", 1) => execute_request(actor, Request::DHTFindOwner { key_id: chord::create_id(&args[0]) }),
+        ("status", 0) => Ok(serde_json::to_value(input::get_status(actor)?)?),
+        ("export", 1) => Ok(serde_json::to_value(snapshot::export(actor, &args[0], false, |line| info!("{}", line))?)?),
+        ("export-ring", 1) => Ok(serde_json::to_value(snapshot::export(actor, &args[0], true, |line| info!("{}", line))?)?),
+        ("import", 1) => Ok(serde_json::to_value(snapshot::import(actor, &args[0], |line| info!("{}", line))?)?),
+        ("kill", 1) => {
+            input::kill_node(args[0].parse::<SocketAddr>()?);
+            Ok(Value::Null)
+        }
+        ("sleep", 1) => {
+            thread::sleep(Duration::from_millis(args[0].parse::<u64>()?));
+            Ok(Value::Null)
+        }
+        ("shutdown", 0) => Ok(Value::Null),
+        (command, _) => Err(ChordError::InvalidArgument(format!("unknown command or wrong number of arguments: '{}'", command))),
+    }
+}
+
+/// Sends a DHT request and waits for the result on its own reply channel,
+/// so late results of earlier commands can't be taken for the result of this one
+fn execute_request(actor: &Sender<Command>, request: Request) -> Result<Value, ChordError> {
+    let result = input::execute_request(actor, request, chord::OPERATION_RESULT_TIMEOUT)?;
+    Ok(serde_json::to_value(result)?)
+}
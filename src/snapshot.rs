@@ -0,0 +1,280 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::sync::mpsc;
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::time::Duration;
+
+use num_bigint::BigInt;
+
+use super::actor::Command;
+use super::chord;
+use super::error::ChordError;
+use super::node::OtherNode;
+use super::operation::OperationResult;
+use super::storage::DHTEntry;
+
+/// Line of a snapshot file, every key is written as one JSON object per line.
+/// Entries keep their version, so an import does not overwrite newer values in the ring.
+#[derive(Serialize, Deserialize, Debug)]
+struct SnapshotEntry {
+    key: String,
+    value: String,
+    version: u64,
+    /// Remaining time to live at the moment of the export, absent if the key never expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    ttl_ms: Option<u64>,
+}
+
+impl SnapshotEntry {
+    fn new(entry: &DHTEntry) -> SnapshotEntry {
+        SnapshotEntry {
+            key: entry.get_key().clone(),
+            value: entry.get_value().clone(),
+            version: entry.get_version(),
+            ttl_ms: entry.get_remaining_ttl().map(|ttl| ttl.as_secs() * 1000 + u64::from(ttl.subsec_millis())),
+        }
+    }
+
+    fn into_data(self) -> (BigInt, DHTEntry) {
+        let key_id = chord::create_id(&self.key);
+        let ttl = self.ttl_ms.map(Duration::from_millis);
+        (key_id, DHTEntry::with_version(self.key, self.value, self.version, ttl))
+    }
+}
+
+/// Progress of an export, sent by the node actor to the exporting thread
+pub enum ExportEvent {
+    /// Keys stored by one node of the ring
+    Collected {
+        node: OtherNode,
+        data: Vec<(BigInt, DHTEntry)>,
+    },
+    /// All nodes have been visited
+    Finished,
+    Failed(ChordError),
+}
+
+/// Result of one imported entry, sent by the node actor to the importing thread
+pub enum ImportEvent {
+    Stored,
+    /// The owner kept a newer version of the key
+    Skipped,
+    Failed {
+        key: String,
+        error: String,
+    },
+    /// Another import is still running
+    Rejected(ChordError),
+}
+
+/// Ring walk of an export, kept by the node that started it
+pub struct PendingExport {
+    visited: HashSet<BigInt>,
+    events: Sender<ExportEvent>,
+}
+
+impl PendingExport {
+    pub fn new(start: BigInt, events: Sender<ExportEvent>) -> PendingExport {
+        let mut visited = HashSet::new();
+        visited.insert(start);
+        PendingExport { visited, events }
+    }
+
+    /// Reports the keys of a node, returns the next node to visit or `None` if the walk is over
+    pub fn collect(&mut self, node: OtherNode, data: Vec<(BigInt, DHTEntry)>, successor: OtherNode) -> Option<OtherNode> {
+        self.visited.insert(node.get_id().clone());
+        if self.events.send(ExportEvent::Collected { node, data }).is_err() {
+            // The exporting thread gave up
+            return None;
+        }
+        if self.visited.contains(successor.get_id()) {
+            let _ = self.events.send(ExportEvent::Finished);
+            None
+        } else {
+            Some(successor)
+        }
+    }
+
+    pub fn fail(self, error: ChordError) {
+        let _ = self.events.send(ExportEvent::Failed(error));
+    }
+}
+
+/// Import that is routed through the ring by the node that started it. Only a window of
+/// entries is in flight at the same time, so a large file does not flood the ring.
+/// Every entry is sent as an operation of its own, results are matched by the operation id.
+pub struct PendingImport {
+    queue: VecDeque<(BigInt, DHTEntry)>,
+    /// Keys of the entries in flight, by operation id
+    in_flight: HashMap<u64, String>,
+    events: Sender<ImportEvent>,
+}
+
+impl PendingImport {
+    pub fn new(data: Vec<(BigInt, DHTEntry)>, events: Sender<ImportEvent>) -> PendingImport {
+        PendingImport { queue: data.into_iter().collect(), in_flight: HashMap::new(), events }
+    }
+
+    /// Takes the entries to send until the window is full, each with an operation id of `create_id`
+    pub fn next_entries<F>(&mut self, mut create_id: F) -> Vec<(u64, (BigInt, DHTEntry))>
+        where F: FnMut() -> u64 {
+        let mut entries = Vec::new();
+        while self.in_flight.len() < chord::IMPORT_WINDOW_SIZE {
+            let data = match self.queue.pop_front() {
+                Some(data) => data,
+                None => break,
+            };
+            let operation = create_id();
+            self.in_flight.insert(operation, data.1.get_key().clone());
+            entries.push((operation, data));
+        }
+        entries
+    }
+
+    pub fn is_in_flight(&self, operation: u64) -> bool {
+        self.in_flight.contains_key(&operation)
+    }
+
+    /// Reports the result of an entry in flight. Returns false if the operation does not belong to the import.
+    pub fn answer(&mut self, operation: u64, result: Result<OperationResult, ChordError>) -> bool {
+        let key = match self.in_flight.remove(&operation) {
+            Some(key) => key,
+            None => return false,
+        };
+        let event = match result {
+            Ok(OperationResult::Kept(_)) => ImportEvent::Skipped,
+            Ok(_) => ImportEvent::Stored,
+            Err(error) => ImportEvent::Failed { key, error: error.to_string() },
+        };
+        if self.events.send(event).is_err() {
+            // The importing thread gave up, only wait for the entries in flight
+            self.queue.clear();
+        }
+        true
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.queue.is_empty() && self.in_flight.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportSummary {
+    path: String,
+    nodes: usize,
+    keys: usize,
+}
+
+impl fmt::Display for ExportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Exported {} keys of {} nodes to {}", self.keys, self.nodes, self.path)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportSummary {
+    path: String,
+    stored: usize,
+    /// Entries not stored because the ring has a newer version of the key
+    skipped: usize,
+    failed: usize,
+    /// Invalid lines, failed stores and entries without an answer
+    errors: Vec<String>,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Imported {} of {} entries from {}, {} skipped (newer version exists), {} failed",
+               self.stored, self.stored + self.skipped + self.failed, self.path, self.skipped, self.failed)?;
+        for error in &self.errors {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the keys of this node, or of all nodes if `ring` is set, to a JSON lines file.
+/// The ring is walked from successor to successor until it wraps around to this node.
+pub fn export<F>(actor: &Sender<Command>, path: &str, ring: bool, mut progress: F) -> Result<ExportSummary, ChordError>
+    where F: FnMut(String) {
+    let (events, receiver) = mpsc::channel();
+    actor.send(Command::Export { ring, events }).map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+    let mut data = Vec::new();
+    let mut nodes = 0;
+    loop {
+        match receiver.recv_timeout(chord::SNAPSHOT_TIMEOUT) {
+            Ok(ExportEvent::Collected { node, data: keys }) => {
+                nodes += 1;
+                progress(format!("Collected {} keys from node #{} ({})", keys.len(), node.get_id(), node.get_ip_addr()));
+                data.extend(keys);
+            }
+            Ok(ExportEvent::Finished) => break,
+            Ok(ExportEvent::Failed(error)) => return Err(error),
+            Err(RecvTimeoutError::Timeout) => return Err(ChordError::Io("export timed out, the ring did not answer".to_string())),
+            Err(RecvTimeoutError::Disconnected) => return Err(ChordError::Io("node actor stopped".to_string())),
+        }
+    }
+
+    let file = File::create(path).map_err(|e| ChordError::Io(format!("snapshot FILE '{}': {}", path, e)))?;
+    let mut writer = BufWriter::new(file);
+    for (_, entry) in &data {
+        serde_json::to_writer(&mut writer, &SnapshotEntry::new(entry))?;
+        writeln!(writer)?;
+    }
+    writer.flush()?;
+    info!("Exported {} keys of {} nodes to {}", data.len(), nodes, path);
+    Ok(ExportSummary { path: path.to_string(), nodes, keys: data.len() })
+}
+
+/// Stores every entry of a JSON lines file via the node responsible for its key
+pub fn import<F>(actor: &Sender<Command>, path: &str, mut progress: F) -> Result<ImportSummary, ChordError>
+    where F: FnMut(String) {
+    let mut data = Vec::new();
+    let mut errors = Vec::new();
+    let file = File::open(path).map_err(|e| ChordError::Io(format!("snapshot FILE '{}': {}", path, e)))?;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<SnapshotEntry>(&line) {
+            Ok(entry) => data.push(entry.into_data()),
+            Err(e) => errors.push(format!("line {}: {}", index + 1, e)),
+        }
+    }
+    let mut summary = ImportSummary { path: path.to_string(), stored: 0, skipped: 0, failed: errors.len(), errors };
+    let total = data.len();
+    if total == 0 {
+        return Ok(summary);
+    }
+
+    let (events, receiver) = mpsc::channel();
+    actor.send(Command::Import { data, events }).map_err(|_| ChordError::Io("node actor stopped".to_string()))?;
+    let step = (total / 10).max(1);
+    let mut answered = 0;
+    while answered < total {
+        match receiver.recv_timeout(chord::SNAPSHOT_TIMEOUT) {
+            Ok(ImportEvent::Stored) => summary.stored += 1,
+            Ok(ImportEvent::Skipped) => summary.skipped += 1,
+            Ok(ImportEvent::Failed { key, error }) => {
+                summary.failed += 1;
+                summary.errors.push(format!("key '{}': {}", key, error));
+            }
+            Ok(ImportEvent::Rejected(error)) => return Err(error),
+            Err(RecvTimeoutError::Timeout) => {
+                summary.failed += total - answered;
+                summary.errors.push(format!("{} entries were not answered in time", total - answered));
+                break;
+            }
+            Err(RecvTimeoutError::Disconnected) => return Err(ChordError::Io("node actor stopped".to_string())),
+        }
+        answered += 1;
+        if answered % step == 0 || answered == total {
+            progress(format!("Imported {}/{} entries ({} skipped, {} failed)", answered, total, summary.skipped, summary.failed));
+        }
+    }
+    info!("Imported {} of {} entries from {}, {} skipped", summary.stored, summary.stored + summary.skipped + summary.failed, path, summary.skipped);
+    Ok(summary)
+}
@@ -1,21 +1,43 @@
 use std::collections::hash_map::Iter;
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::{Duration, Instant};
 
 use chrono::{DateTime, Local};
 use colored::*;
 use num::bigint::BigInt;
 
 use super::chord;
+use super::node::OtherNode;
 
+/// Key/value pair stored in the DHT.
+/// `version` is a Lamport timestamp assigned by the node responsible for the key,
+/// a version of `0` marks a fresh write that has not been stamped yet.
+/// `ttl` is the remaining time to live at the moment the entry was sent over the network,
+/// the local `expires_at` deadline is derived from it once the entry is stored.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct DHTEntry {
     key: String,
     value: String,
+    version: u64,
+    ttl: Option<Duration>,
+    #[serde(skip)]
+    expires_at: Option<Instant>,
 }
 
 impl DHTEntry {
     pub fn new(key: String, value: String) -> DHTEntry {
-        DHTEntry { key, value }
+        DHTEntry { key, value, version: 0, ttl: None, expires_at: None }
+    }
+
+    pub fn with_ttl(key: String, value: String, ttl: Option<Duration>) -> DHTEntry {
+        DHTEntry { ttl, ..DHTEntry::new(key, value) }
+    }
+
+    /// Entry that keeps the version it had in the ring, e.g. when restored from a snapshot
+    pub fn with_version(key: String, value: String, version: u64, ttl: Option<Duration>) -> DHTEntry {
+        DHTEntry { version, ttl, ..DHTEntry::new(key, value) }
     }
 
     pub fn get_key(&self) -> &String {
@@ -25,27 +47,257 @@ impl DHTEntry {
     pub fn get_value(&self) -> &String {
         &self.value
     }
+
+    pub fn get_version(&self) -> u64 {
+        self.version
+    }
+
+    /// Remaining time to live, `None` if the entry never expires
+    pub fn get_remaining_ttl(&self) -> Option<Duration> {
+        match self.expires_at {
+            Some(expires_at) => Some(expires_at.saturating_duration_since(Instant::now())),
+            None => self.ttl,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+
+    /// Anchors the received ttl to the local clock
+    fn start_expiry(&mut self) {
+        if self.expires_at.is_none() {
+            self.expires_at = self.ttl.map(|ttl| Instant::now() + ttl);
+        }
+    }
+
+    /// Copy of the entry that carries the remaining ttl, used when sending it to another node
+    fn to_transferable(&self) -> DHTEntry {
+        DHTEntry { ttl: self.get_remaining_ttl(), expires_at: None, ..self.clone() }
+    }
+
+    /// Returns true if `self` should win over `other` when both describe the same key.
+    /// Equal versions with different values are a conflict, which is resolved
+    /// deterministically by the value so that every node ends up with the same entry.
+    fn supersedes(&self, other: &DHTEntry) -> bool {
+        self.version > other.version || (self.version == other.version && self.value > other.value)
+    }
+}
+
+/// Marks a deleted key with the version of the delete, so a stale copy of the key that arrives
+/// later (e.g. from a node that has not handed it over yet) can't bring back the old value.
+/// Tombstones move along with the keys, `lifetime` is the remaining time at the moment the
+/// tombstone was sent over the network.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Tombstone {
+    version: u64,
+    lifetime: Duration,
+    #[serde(skip)]
+    expires_at: Option<Instant>,
+}
+
+impl Tombstone {
+    fn new(version: u64) -> Tombstone {
+        let mut tombstone = Tombstone { version, lifetime: chord::TOMBSTONE_INTERVAL, expires_at: None };
+        tombstone.start_expiry();
+        tombstone
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+
+    fn start_expiry(&mut self) {
+        if self.expires_at.is_none() {
+            self.expires_at = Some(Instant::now() + self.lifetime);
+        }
+    }
+
+    fn to_transferable(&self) -> Tombstone {
+        let lifetime = self.expires_at.map_or(self.lifetime, |expires_at| expires_at.saturating_duration_since(Instant::now()));
+        Tombstone { lifetime, expires_at: None, ..self.clone() }
+    }
+}
+
+/// Precondition of a conditional write
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum WriteCondition {
+    /// Only write if there is no (unexpired) entry for the key
+    IfAbsent,
+    /// Only write if the current version matches, version `0` means absent
+    IfVersion(u64),
+}
+
+/// Lease of a node on a key it watches or a topic it subscribed to. It is renewed by the node
+/// and expires if the node dies. Like the ttl of an entry, `lease` is the remaining time at the
+/// moment the lease was sent over the network.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Lease {
+    holder: OtherNode,
+    lease: Duration,
+    #[serde(skip)]
+    expires_at: Option<Instant>,
+}
+
+impl Lease {
+    pub fn new(holder: OtherNode) -> Lease {
+        Lease { holder, lease: chord::LEASE_INTERVAL, expires_at: None }
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= Instant::now())
+    }
+
+    fn start_expiry(&mut self) {
+        if self.expires_at.is_none() {
+            self.expires_at = Some(Instant::now() + self.lease);
+        }
+    }
+
+    fn to_transferable(&self) -> Lease {
+        let lease = self.expires_at.map_or(self.lease, |expires_at| expires_at.saturating_duration_since(Instant::now()));
+        Lease { lease, expires_at: None, ..self.clone() }
+    }
+}
+
+/// Leases by key or topic id, for the ids this node is responsible for. They move along with the keys.
+#[derive(Clone)]
+pub struct LeaseTable {
+    leases: HashMap<BigInt, Vec<Lease>>,
+}
+
+impl LeaseTable {
+    pub fn new() -> LeaseTable {
+        LeaseTable { leases: HashMap::new() }
+    }
+
+    /// Adds a lease, replacing an earlier lease of the same node unless that one lasts longer
+    pub fn add(&mut self, id: BigInt, mut lease: Lease) {
+        lease.start_expiry();
+        let leases = self.leases.entry(id).or_default();
+        if let Some(existing) = leases.iter_mut().find(|existing| existing.holder.get_id() == lease.holder.get_id()) {
+            if existing.expires_at < lease.expires_at {
+                *existing = lease;
+            }
+        } else {
+            leases.push(lease);
+        }
+    }
+
+    /// Nodes holding a lease on the id that has not expired yet
+    pub fn get_holders(&self, id: &BigInt) -> Vec<OtherNode> {
+        self.leases.get(id)
+            .map(|leases| leases.iter()
+                .filter(|lease| !lease.is_expired())
+                .map(|lease| lease.holder.clone())
+                .collect())
+            .unwrap_or_default()
+    }
+
+    pub fn get_ids(&self) -> Vec<BigInt> {
+        self.leases.keys().cloned().collect()
+    }
+
+    /// All leases that are not expired yet, ready to be handed over together with the keys
+    pub fn get_as_vec(&self) -> Vec<(BigInt, Lease)> {
+        self.leases.iter()
+            .flat_map(|(id, leases)| leases.iter()
+                .filter(|lease| !lease.is_expired())
+                .map(move |lease| (id.clone(), lease.to_transferable())))
+            .collect()
+    }
+
+    /// Removes the leases of an id that moves to another node, ready to be handed over
+    pub fn remove(&mut self, id: &BigInt) -> Vec<Lease> {
+        self.leases.remove(id).unwrap_or_default().iter()
+            .filter(|lease| !lease.is_expired())
+            .map(|lease| lease.to_transferable())
+            .collect()
+    }
+
+    /// Removes the leases that were not renewed in time, returns their number
+    pub fn remove_expired(&mut self) -> usize {
+        let mut removed = 0;
+        for leases in self.leases.values_mut() {
+            let before = leases.len();
+            leases.retain(|lease| !lease.is_expired());
+            removed += before - leases.len();
+        }
+        self.leases.retain(|_, leases| !leases.is_empty());
+        removed
+    }
+}
+
+/// Entry of the storage log, e.g. the result of a request sent by this node
+#[derive(Clone, Debug)]
+pub struct LogEntry {
+    time: DateTime<Local>,
+    message: String,
+}
+
+impl LogEntry {
+    pub fn get_time(&self) -> &DateTime<Local> {
+        &self.time
+    }
+
+    pub fn get_message(&self) -> &String {
+        &self.message
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.time.format("%H:%M:%S").to_string().yellow(), self.message)
+    }
 }
 
 #[derive(Clone)]
 pub struct Storage {
     data: HashMap<BigInt, DHTEntry>,
-    logs: Vec<String>,
-
+    /// Deleted keys, kept for `chord::TOMBSTONE_INTERVAL`
+    tombstones: HashMap<BigInt, Tombstone>,
+    /// Lamport clock, the highest version written or seen by this node
+    clock: u64,
+    /// Watches of the keys this node is responsible for, also of keys that are not present
+    watches: LeaseTable,
+    /// Subscriptions of the topics this node is the rendezvous node for
+    subscriptions: LeaseTable,
+    /// The last `chord::LOG_ENTRIES_LIMIT` entries of the log
+    logs: VecDeque<LogEntry>,
+    /// Number of entries dropped from the front of the log, the index of the first entry kept
+    dropped_logs: usize,
 }
 
 impl Storage {
     pub fn new() -> Storage {
         Storage {
             data: HashMap::new(),
-            logs: Vec::new(),
+            tombstones: HashMap::new(),
+            clock: 0,
+            watches: LeaseTable::new(),
+            subscriptions: LeaseTable::new(),
+            logs: VecDeque::new(),
+            dropped_logs: 0,
         }
     }
 
+    /// All entries that are not expired yet, ready to be handed over to another node
     pub fn get_data_as_vec(&self) -> Vec<(BigInt, DHTEntry)> {
-        self.data.iter().map(|(id, entry)| (id.clone(), entry.clone())).collect()
+        self.data.iter()
+            .filter(|(_, entry)| !entry.is_expired())
+            .map(|(id, entry)| (id.clone(), entry.to_transferable()))
+            .collect()
+    }
+
+    /// All tombstones that are not expired yet, ready to be handed over along with the keys
+    pub fn get_tombstones_as_vec(&self) -> Vec<(BigInt, Tombstone)> {
+        self.tombstones.iter()
+            .filter(|(_, tombstone)| !tombstone.is_expired())
+            .map(|(id, tombstone)| (id.clone(), tombstone.to_transferable()))
+            .collect()
     }
-    pub fn get_data_as_iter(&self) -> Iter<BigInt, DHTEntry> {
+
+    pub fn get_data_as_iter(&self) -> Iter<'_, BigInt, DHTEntry> {
         self.data.iter()
     }
 
@@ -53,34 +305,189 @@ impl Storage {
         self.data.is_empty()
     }
 
-    pub fn store_key(&mut self, data: (BigInt, DHTEntry)) {
-        self.data.insert(data.0, data.1);
+    /// Next Lamport timestamp for a write or delete of this node
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Stores a new write for a key this node is responsible for.
+    /// Unstamped entries are stamped with the next tick of the Lamport clock, which is newer than
+    /// every version this node has written or seen, including deleted ones. Already versioned
+    /// entries (e.g. redistributed keys) are merged.
+    /// Returns the stored version, or the version that is kept if the entry is not newer than the
+    /// current entry or the delete of the key (`0` if the key is absent).
+    pub fn store_key(&mut self, data: (BigInt, DHTEntry)) -> Result<u64, u64> {
+        let (key_id, mut entry) = data;
+        if entry.version == 0 {
+            entry.version = self.tick();
+        }
+        let applied = self.merge_key((key_id.clone(), entry));
+        let version = self.data.get(&key_id).map_or(0, |entry| entry.version);
+        if applied { Ok(version) } else { Err(version) }
+    }
+
+    /// Stores a new write only if the given condition holds for the current entry.
+    /// Returns the stored version, or the current entry if the write was rejected.
+    pub fn store_key_if(&mut self, data: (BigInt, DHTEntry), condition: &WriteCondition) -> Result<u64, Option<DHTEntry>> {
+        let current = self.get_key(&data.0);
+        let current_version = current.as_ref().map_or(0, |entry| entry.version);
+        let applies = match condition {
+            WriteCondition::IfAbsent => current.is_none(),
+            WriteCondition::IfVersion(version) => *version == current_version,
+        };
+        if applies {
+            let (key_id, entry) = data;
+            let entry = DHTEntry { version: 0, ..entry };
+            // A new tick of the clock is newer than every version, so the write is always applied
+            match self.store_key((key_id, entry)) {
+                Ok(version) | Err(version) => Ok(version),
+            }
+        } else {
+            Err(current)
+        }
     }
 
-    pub fn get_key(&self, key_id: &BigInt) -> Option<&DHTEntry> {
+    /// Merges an entry into the storage, keeping the newest version. Entries that are not newer
+    /// than the delete of the key are dropped. Returns true if the given entry was applied.
+    pub fn merge_key(&mut self, data: (BigInt, DHTEntry)) -> bool {
+        let (key_id, mut entry) = data;
+        entry.start_expiry();
+        self.clock = cmp::max(self.clock, entry.version);
+        if let Some(tombstone) = self.tombstones.get(&key_id) {
+            if !tombstone.is_expired() && tombstone.version >= entry.version {
+                debug!("Dropping key '{}' with version {}, it was deleted with version {}",
+                       entry.key, entry.version, tombstone.version);
+                return false;
+            }
+        }
+        let apply = match self.data.get(&key_id) {
+            Some(existing) if !existing.is_expired() => {
+                if existing.version == entry.version && existing.value != entry.value {
+                    warn!("Conflicting values for key '{}' with version {}: '{}' vs. '{}'",
+                          entry.key, entry.version, existing.value, entry.value);
+                }
+                entry.supersedes(existing)
+            }
+            _ => true,
+        };
+        if apply {
+            self.tombstones.remove(&key_id);
+            self.data.insert(key_id, entry);
+        }
+        apply
+    }
+
+    /// Returns the entry for the given key, expired entries are treated as absent
+    pub fn get_key(&self, key_id: &BigInt) -> Option<DHTEntry> {
         self.data.get(key_id)
+            .filter(|entry| !entry.is_expired())
+            .map(|entry| entry.to_transferable())
     }
 
+    /// Deletes a key and leaves a tombstone with a new version, returns the deleted entry
     pub fn delete_key(&mut self, key_id: &BigInt) -> Option<DHTEntry> {
+        let deleted = self.data.remove(key_id).filter(|entry| !entry.is_expired());
+        if deleted.is_some() {
+            let tombstone = Tombstone::new(self.tick());
+            self.tombstones.insert(key_id.clone(), tombstone);
+        }
+        deleted
+    }
+
+    /// Merges the tombstone of a key deleted on another node. The entry of the key is removed
+    /// unless it is newer than the delete. Returns true if the tombstone was applied.
+    pub fn merge_tombstone(&mut self, key_id: BigInt, mut tombstone: Tombstone) -> bool {
+        tombstone.start_expiry();
+        self.clock = cmp::max(self.clock, tombstone.version);
+        if tombstone.is_expired() {
+            return false;
+        }
+        if let Some(existing) = self.tombstones.get(&key_id) {
+            if !existing.is_expired() && existing.version >= tombstone.version {
+                return false;
+            }
+        }
+        if let Some(entry) = self.data.get(&key_id) {
+            if !entry.is_expired() && entry.version > tombstone.version {
+                return false;
+            }
+        }
+        self.data.remove(&key_id);
+        self.tombstones.insert(key_id, tombstone);
+        true
+    }
+
+    /// Removes a key that moves to another node, without a tombstone
+    pub fn remove_key(&mut self, key_id: &BigInt) -> Option<DHTEntry> {
         self.data.remove(key_id)
     }
 
+    /// Removes the tombstone of a key that moves to another node, ready to be handed over
+    pub fn remove_tombstone(&mut self, key_id: &BigInt) -> Option<Tombstone> {
+        self.tombstones.remove(key_id)
+            .filter(|tombstone| !tombstone.is_expired())
+            .map(|tombstone| tombstone.to_transferable())
+    }
+
+    /// Removes all expired entries and tombstones, returns the ids and keys of the entries
+    pub fn remove_expired_keys(&mut self) -> Vec<(BigInt, String)> {
+        self.tombstones.retain(|_, tombstone| !tombstone.is_expired());
+        let expired: Vec<BigInt> = self.data.iter()
+            .filter(|(_, entry)| entry.is_expired())
+            .map(|(id, _)| id.clone())
+            .collect();
+        expired.into_iter()
+            .filter_map(|id| self.data.remove(&id).map(|entry| (id, entry.key)))
+            .collect()
+    }
+
+    pub fn get_watches(&self) -> &LeaseTable {
+        &self.watches
+    }
+
+    pub fn get_watches_mut(&mut self) -> &mut LeaseTable {
+        &mut self.watches
+    }
+
+    pub fn get_subscriptions(&self) -> &LeaseTable {
+        &self.subscriptions
+    }
+
+    pub fn get_subscriptions_mut(&mut self) -> &mut LeaseTable {
+        &mut self.subscriptions
+    }
+
     pub fn write_log_entry(&mut self, str: String) {
-        let local: DateTime<Local> = Local::now();
-        self.logs.push(format!("{} {}", local.format("%H:%M:%S").to_string().yellow(), str));
+        self.logs.push_back(LogEntry { time: Local::now(), message: str });
+        if self.logs.len() > chord::LOG_ENTRIES_LIMIT {
+            self.logs.pop_front();
+            self.dropped_logs += 1;
+        }
+    }
+
+    pub fn get_log_entries(&self) -> &VecDeque<LogEntry> {
+        &self.logs
+    }
+
+    /// Entries starting at index `since` and the index of the next entry. Indices keep counting
+    /// when old entries are dropped, so a reader continues where it stopped.
+    pub fn get_log_entries_since(&self, since: usize) -> (Vec<LogEntry>, usize) {
+        let entries = self.logs.iter().skip(since.saturating_sub(self.dropped_logs)).cloned().collect();
+        (entries, self.dropped_logs + self.logs.len())
     }
 
     pub fn get_last_three_log_entries(&self) -> Vec<String> {
         let mut last_three_entries = Vec::new();
         if self.logs.len() >= 3 {
-            last_three_entries.push(self.logs[self.logs.len() - 3].clone());
-            last_three_entries.push(self.logs[self.logs.len() - 2].clone());
-            last_three_entries.push(self.logs[self.logs.len() - 1].clone());
+            last_three_entries.push(self.logs[self.logs.len() - 3].to_string());
+            last_three_entries.push(self.logs[self.logs.len() - 2].to_string());
+            last_three_entries.push(self.logs[self.logs.len() - 1].to_string());
         } else if self.logs.len() == 2 {
-            last_three_entries.push(self.logs[self.logs.len() - 2].clone());
-            last_three_entries.push(self.logs[self.logs.len() - 1].clone());
+            last_three_entries.push(self.logs[self.logs.len() - 2].to_string());
+            last_three_entries.push(self.logs[self.logs.len() - 1].to_string());
         } else if self.logs.len() == 1 {
-            last_three_entries.push(self.logs[self.logs.len() - 1].clone());
+            last_three_entries.push(self.logs[self.logs.len() - 1].to_string());
         } else {
             last_three_entries.push("No log entry found".italic().yellow().to_string())
         }
@@ -88,7 +495,86 @@ impl Storage {
     }
 }
 
-pub fn make_hashed_key_value_pair(key: String, value: String) -> (BigInt, DHTEntry) {
+pub fn make_hashed_key_value_pair(key: String, value: String, ttl: Option<Duration>) -> (BigInt, DHTEntry) {
     let id = chord::create_id(&key);
-    (id, DHTEntry::new(key, value))
+    (id, DHTEntry::with_ttl(key, value, ttl))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{DHTEntry, Storage, WriteCondition};
+    use super::super::chord;
+
+    fn entry(value: &str, version: u64) -> DHTEntry {
+        DHTEntry::with_version("key".to_string(), value.to_string(), version, None)
+    }
+
+    #[test]
+    fn equal_versions_resolve_to_the_same_value_on_every_node() {
+        let key_id = chord::create_id("key");
+        let mut first = Storage::new();
+        first.merge_key((key_id.clone(), entry("a", 3)));
+        first.merge_key((key_id.clone(), entry("b", 3)));
+        let mut second = Storage::new();
+        second.merge_key((key_id.clone(), entry("b", 3)));
+        second.merge_key((key_id.clone(), entry("a", 3)));
+
+        assert_eq!(first.get_key(&key_id).unwrap().get_value(), "b");
+        assert_eq!(second.get_key(&key_id).unwrap().get_value(), "b");
+        assert!(!first.merge_key((key_id.clone(), entry("c", 2))));
+        assert_eq!(first.get_key(&key_id).unwrap().get_value(), "b");
+    }
+
+    #[test]
+    fn entries_expire_after_their_ttl() {
+        let mut storage = Storage::new();
+        let key_id = chord::create_id("key");
+        let ttl = Duration::from_secs(60);
+        storage.store_key((key_id.clone(), DHTEntry::with_ttl("key".to_string(), "value".to_string(), Some(ttl)))).unwrap();
+        let remaining = storage.get_key(&key_id).unwrap().get_remaining_ttl().unwrap();
+        assert!(remaining <= ttl && remaining > ttl - Duration::from_secs(5));
+        assert_eq!(DHTEntry::new("key".to_string(), "value".to_string()).get_remaining_ttl(), None);
+
+        let expired_id = chord::create_id("expired");
+        let expired = DHTEntry::with_ttl("expired".to_string(), "value".to_string(), Some(Duration::from_secs(0)));
+        storage.store_key((expired_id.clone(), expired)).unwrap();
+        assert!(storage.get_key(&expired_id).is_none());
+        assert_eq!(storage.remove_expired_keys(), vec![(expired_id, "expired".to_string())]);
+        assert!(storage.get_key(&key_id).is_some());
+    }
+
+    #[test]
+    fn compare_and_swap_needs_the_current_version() {
+        let mut storage = Storage::new();
+        let key_id = chord::create_id("key");
+        let version = storage.store_key((key_id.clone(), entry("a", 0))).unwrap();
+
+        let rejected = storage.store_key_if((key_id.clone(), entry("b", 0)), &WriteCondition::IfVersion(version + 1));
+        let current = rejected.unwrap_err().unwrap();
+        assert_eq!((current.get_value().as_str(), current.get_version()), ("a", version));
+
+        let swapped = storage.store_key_if((key_id.clone(), entry("b", 0)), &WriteCondition::IfVersion(version)).unwrap();
+        assert!(swapped > version);
+        assert_eq!(storage.get_key(&key_id).unwrap().get_value(), "b");
+        let absent = DHTEntry::new("other".to_string(), "c".to_string());
+        assert!(storage.store_key_if((chord::create_id("other"), absent), &WriteCondition::IfVersion(0)).is_ok());
+    }
+
+    #[test]
+    fn put_if_absent_only_writes_missing_or_expired_keys() {
+        let mut storage = Storage::new();
+        let key_id = chord::create_id("key");
+        storage.store_key((key_id.clone(), entry("a", 0))).unwrap();
+        let rejected = storage.store_key_if((key_id.clone(), entry("b", 0)), &WriteCondition::IfAbsent);
+        assert_eq!(rejected.unwrap_err().unwrap().get_value(), "a");
+
+        let expired_id = chord::create_id("expired");
+        let expired = DHTEntry::with_ttl("expired".to_string(), "a".to_string(), Some(Duration::from_secs(0)));
+        storage.store_key((expired_id.clone(), expired)).unwrap();
+        let write = DHTEntry::new("expired".to_string(), "b".to_string());
+        assert!(storage.store_key_if((expired_id.clone(), write), &WriteCondition::IfAbsent).is_ok());
+        assert_eq!(storage.get_key(&expired_id).unwrap().get_value(), "b");
+    }
 }
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use super::chord;
+
+/// Reasons for the listener to reject inbound traffic
+#[derive(Clone, Copy, Debug)]
+pub enum Rejection {
+    Oversized,
+    Timeout,
+    ConnectionLimit,
+    RateLimit,
+    Malformed,
+}
+
+const REJECTIONS: [Rejection; 5] = [
+    Rejection::Oversized,
+    Rejection::Timeout,
+    Rejection::ConnectionLimit,
+    Rejection::RateLimit,
+    Rejection::Malformed,
+];
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Rejection::Oversized => "oversized",
+            Rejection::Timeout => "timeout",
+            Rejection::ConnectionLimit => "connection limit",
+            Rejection::RateLimit => "rate limit",
+            Rejection::Malformed => "malformed",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Counters of rejected inbound traffic, shared between the listener and the node
+#[derive(Default)]
+pub struct TrafficStats {
+    rejected: [AtomicUsize; 5],
+}
+
+impl TrafficStats {
+    pub fn new() -> TrafficStats {
+        TrafficStats::default()
+    }
+
+    pub fn record(&self, rejection: Rejection) {
+        self.rejected[rejection as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self, rejection: Rejection) -> usize {
+        self.rejected[rejection as usize].load(Ordering::Relaxed)
+    }
+
+    pub fn get_all(&self) -> Vec<(Rejection, usize)> {
+        REJECTIONS.iter().map(|rejection| (*rejection, self.get(*rejection))).collect()
+    }
+}
+
+/// Limits the number of concurrently processed inbound connections
+pub struct ConnectionLimiter {
+    active: Arc<AtomicUsize>,
+}
+
+/// Slot of an accepted connection, released when dropped
+pub struct ConnectionPermit {
+    active: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimiter {
+    pub fn new() -> ConnectionLimiter {
+        ConnectionLimiter { active: Arc::new(AtomicUsize::new(0)) }
+    }
+
+    pub fn try_acquire(&self) -> Option<ConnectionPermit> {
+        if self.active.fetch_add(1, Ordering::SeqCst) < chord::MAX_INBOUND_CONNECTIONS {
+            Some(ConnectionPermit { active: self.active.clone() })
+        } else {
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            None
+        }
+    }
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket rate limit per peer ip address.
+/// Nodes running on the same host share their budget.
+pub struct RateLimiter {
+    buckets: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> RateLimiter {
+        RateLimiter { buckets: Mutex::new(HashMap::new()) }
+    }
+
+    /// Takes a token for the given peer, returns false if the peer exceeded its rate
+    pub fn allow(&self, ip: IpAddr) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= chord::MAX_RATE_LIMITED_PEERS && !buckets.contains_key(&ip) {
+            make_room(&mut buckets, now);
+        }
+        let bucket = buckets.entry(ip).or_insert(TokenBucket {
+            tokens: chord::PEER_RATE_LIMIT_BURST,
+            last_refill: now,
+        });
+        if refill(bucket, now) >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Frees a slot for a new peer. Peers that have been idle long enough to have a full bucket
+/// again are forgotten, if every peer is active the one seen least recently is forgotten instead.
+fn make_room(buckets: &mut HashMap<IpAddr, TokenBucket>, now: Instant) {
+    buckets.retain(|_, bucket| available(bucket, now) < chord::PEER_RATE_LIMIT_BURST);
+    if buckets.len() >= chord::MAX_RATE_LIMITED_PEERS {
+        let oldest = buckets.iter().min_by_key(|(_, bucket)| bucket.last_refill).map(|(ip, _)| *ip);
+        if let Some(oldest) = oldest {
+            buckets.remove(&oldest);
+        }
+    }
+}
+
+/// Tokens of the bucket at the given time, without taking one
+fn available(bucket: &TokenBucket, now: Instant) -> f64 {
+    let elapsed = now.saturating_duration_since(bucket.last_refill);
+    let elapsed_secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1_000_000_000.0;
+    (bucket.tokens + elapsed_secs * chord::PEER_RATE_LIMIT).min(chord::PEER_RATE_LIMIT_BURST)
+}
+
+fn refill(bucket: &mut TokenBucket, now: Instant) -> f64 {
+    bucket.tokens = available(bucket, now);
+    bucket.last_refill = now;
+    bucket.tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv6Addr};
+    use std::time::Instant;
+
+    use super::{RateLimiter, TokenBucket};
+    use super::super::chord;
+
+    fn peer(index: usize) -> IpAddr {
+        IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, (index >> 16) as u16, index as u16))
+    }
+
+    #[test]
+    fn rate_limiter_keeps_at_most_the_maximum_of_peers() {
+        let limiter = RateLimiter::new();
+        // Peers that used up their burst, they stay active for seconds
+        let now = Instant::now();
+        for index in 0..chord::MAX_RATE_LIMITED_PEERS {
+            limiter.buckets.lock().unwrap().insert(peer(index), TokenBucket { tokens: 0.0, last_refill: now });
+        }
+        let peers = chord::MAX_RATE_LIMITED_PEERS + 100;
+        for index in chord::MAX_RATE_LIMITED_PEERS..peers {
+            assert!(limiter.allow(peer(index)));
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert!(buckets.len() <= chord::MAX_RATE_LIMITED_PEERS);
+        assert!(buckets.contains_key(&peer(peers - 1)));
+    }
+
+    #[test]
+    fn rate_limiter_rejects_a_peer_over_its_burst() {
+        let limiter = RateLimiter::new();
+        let allowed = (0..chord::PEER_RATE_LIMIT_BURST as usize * 2).filter(|_| limiter.allow(peer(0))).count();
+        assert!(allowed >= chord::PEER_RATE_LIMIT_BURST as usize && allowed < chord::PEER_RATE_LIMIT_BURST as usize * 2);
+        assert!(limiter.allow(peer(1)));
+    }
+}